@@ -0,0 +1,371 @@
+//! 不依赖postgres的db721命令行工具
+//!
+//! ```text
+//! db721 info <file>
+//! db721 stats <file> [--column NAME]
+//! db721 cat <file> [--columns a,b] [--offset N] [--limit N] [--format csv|json]
+//! db721 check <file>
+//! db721 repair <file> [--out FILE]
+//! db721 from-csv <csv> --out <file> [--schema a:int,b:str] [--block-size N] [--table NAME]
+//!     [--sample-rows N] [--delimiter C] [--no-header true] [--codec none|lz4] [--encode true]
+//!     [--var-str true] [--bloom a,b]
+//! ```
+#[path = ".."]
+#[allow(dead_code)]
+mod src {
+    pub mod db721;
+}
+use src::db721;
+
+use anyhow::{bail, Context};
+use db721::check::check_file;
+use db721::csv_import::{import_csv, parse_schema, CsvImportOptions};
+use db721::repair::repair_file;
+use db721::{Codec, ColumnCursor, ColumnIteratorBuilder, DB721Type, DB721};
+use std::io::Write;
+use std::path::PathBuf;
+
+const USAGE: &str = "usage:
+    db721 info <file>
+    db721 stats <file> [--column NAME]
+    db721 cat <file> [--columns a,b] [--offset N] [--limit N] [--format csv|json]
+    db721 check <file>
+    db721 repair <file> [--out FILE]
+    db721 from-csv <csv> --out <file> [--schema a:int,b:str] [--block-size N] [--table NAME]
+        [--sample-rows N] [--delimiter C] [--no-header true] [--codec none|lz4] [--encode true]
+        [--var-str true] [--bloom a,b]";
+
+/// 子命令的参数：一个文件加若干 --name value 选项
+struct Args {
+    file: PathBuf,
+    options: Vec<(String, String)>,
+}
+impl Args {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut file = None;
+        let mut options = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let Some(value) = iter.next() else {
+                    bail!("option --{} needs a value", name);
+                };
+                options.push((name.to_string(), value.clone()));
+            } else if file.is_none() {
+                file = Some(PathBuf::from(arg));
+            } else {
+                bail!("unexpected argument {}", arg);
+            }
+        }
+        let Some(file) = file else {
+            bail!("need a db721 file");
+        };
+        Ok(Self { file, options })
+    }
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(option_name, _)| option_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+    fn check_options(&self, allowed: &[&str]) -> anyhow::Result<()> {
+        for (name, _) in self.options.iter() {
+            if !allowed.contains(&name.as_str()) {
+                bail!("unknown option --{}", name);
+            }
+        }
+        Ok(())
+    }
+    fn open(&self) -> anyhow::Result<DB721> {
+        DB721::open(self.file.clone())
+            .with_context(|| format!("failed to open {}", self.file.display()))
+    }
+}
+fn info(args: &Args) -> anyhow::Result<()> {
+    args.check_options(&[])?;
+    let db721 = args.open()?;
+    println!("table: {}", db721.meta.table_name());
+    println!("max values per block: {}", db721.meta.max_value_per_block());
+    println!("rows: {}", db721.row_count());
+    println!("columns:");
+    for (column_name, column_meta) in db721.meta.sorted_columns() {
+        println!(
+            "    {} {} start_offset={} num_blocks={} codec={}",
+            column_name,
+            column_meta.value_type(),
+            column_meta.start_offset(),
+            column_meta.num_blocks(),
+            column_meta.codec().name()
+        );
+    }
+    Ok(())
+}
+fn stats(args: &Args) -> anyhow::Result<()> {
+    args.check_options(&["column"])?;
+    let db721 = args.open()?;
+    let column = args.option("column");
+    if let Some(column) = column {
+        if !db721.meta.column_meta.contains_key(column) {
+            bail!("column {} does not exist", column);
+        }
+    }
+    let mut writer = csv::Writer::from_writer(std::io::stdout().lock());
+    writer.write_record([
+        "column_name",
+        "block_index",
+        "num",
+        "min",
+        "max",
+        "min_len",
+        "max_len",
+        "compressed_size",
+        "encoding",
+        "null_count",
+        "bloom_size",
+    ])?;
+    for (column_name, column_meta) in db721.meta.sorted_columns() {
+        if column.is_some_and(|column| column != column_name) {
+            continue;
+        }
+        for block_idx in 0..column_meta.num_blocks() {
+            let Some(block_meta) = column_meta.block_meta(block_idx) else {
+                continue;
+            };
+            writer.write_record([
+                column_name.clone(),
+                block_idx.to_string(),
+                block_meta.value_num().to_string(),
+                block_meta.min().to_string(),
+                block_meta.max().to_string(),
+                block_meta
+                    .min_len()
+                    .map_or(String::new(), |len| len.to_string()),
+                block_meta
+                    .max_len()
+                    .map_or(String::new(), |len| len.to_string()),
+                block_meta
+                    .compressed_size()
+                    .map_or(String::new(), |size| size.to_string()),
+                block_meta.encoding().name().to_string(),
+                block_meta.null_count().to_string(),
+                block_meta
+                    .bloom()
+                    .map_or(String::new(), |bloom| bloom.size().to_string()),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+fn cat(args: &Args) -> anyhow::Result<()> {
+    args.check_options(&["columns", "offset", "limit", "format"])?;
+    let db721 = args.open()?;
+    let column_names: Vec<String> = match args.option("columns") {
+        Some(columns) => columns
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect(),
+        None => db721
+            .meta
+            .sorted_columns()
+            .into_iter()
+            .map(|(column_name, _)| column_name.clone())
+            .collect(),
+    };
+    let offset: usize = match args.option("offset") {
+        Some(offset) => offset.parse().context("invalid --offset")?,
+        None => 0,
+    };
+    let limit: Option<usize> = match args.option("limit") {
+        Some(limit) => Some(limit.parse().context("invalid --limit")?),
+        None => None,
+    };
+    let json = match args.option("format").unwrap_or("csv") {
+        "csv" => false,
+        "json" => true,
+        format => bail!("unknown format {}, expected csv or json", format),
+    };
+    let mut column_cursors = Vec::with_capacity(column_names.len());
+    for column_name in column_names.iter() {
+        let Some(column_meta) = db721.meta.column_meta.get(column_name) else {
+            bail!("column {} does not exist", column_name);
+        };
+        let column_iterator = ColumnIteratorBuilder::new(
+            column_meta.clone(),
+            column_name.clone(),
+            db721.path.clone(),
+        )
+        .build()?;
+        let mut column_cursor = ColumnCursor::new(column_iterator);
+        // 整块跳过offset之前的block，编码的block也只解码需要的部分
+        column_cursor.skip(offset);
+        column_cursors.push(column_cursor);
+    }
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut csv_writer = csv::Writer::from_writer(std::io::stdout().lock());
+    if !json {
+        csv_writer.write_record(column_names.iter())?;
+    }
+    let mut row_count = 0usize;
+    loop {
+        // NULL以及已经读完的列都为None，CSV中输出空字段，JSON中输出null
+        let mut row: Vec<Option<DB721Type>> = Vec::with_capacity(column_cursors.len());
+        let mut found = false;
+        for column_cursor in column_cursors.iter_mut() {
+            match column_cursor.advance() {
+                Some((batch, value_idx)) => {
+                    row.push(batch.value(value_idx));
+                    found = true;
+                }
+                None => row.push(None),
+            }
+        }
+        if !found {
+            break;
+        }
+        if limit.is_some_and(|limit| row_count >= limit) {
+            break;
+        }
+        if json {
+            write!(out, "{{")?;
+            for (idx, (column_name, value)) in column_names.iter().zip(row.iter()).enumerate() {
+                if idx > 0 {
+                    write!(out, ",")?;
+                }
+                // date和timestamp输出为与CSV中相同的字符串，而不是存储的整数
+                let value = match value {
+                    Some(value @ (DB721Type::Date(_) | DB721Type::Timestamp(_))) => {
+                        serde_json::to_string(&value.to_string())?
+                    }
+                    value => serde_json::to_string(value)?,
+                };
+                write!(out, "{}:{}", serde_json::to_string(column_name)?, value)?;
+            }
+            writeln!(out, "}}")?;
+        } else {
+            csv_writer.write_record(row.iter().map(|value| {
+                value
+                    .as_ref()
+                    .map_or(String::new(), |value| value.to_string())
+            }))?;
+        }
+        row_count += 1;
+    }
+    csv_writer.flush()?;
+    out.flush()?;
+    Ok(())
+}
+fn check(args: &Args) -> anyhow::Result<bool> {
+    args.check_options(&[])?;
+    let findings = check_file(args.file.clone());
+    for finding in findings.iter() {
+        println!("{}", finding);
+    }
+    if findings.is_empty() {
+        println!("{}: ok", args.file.display());
+    }
+    Ok(findings.is_empty())
+}
+fn repair(args: &Args) -> anyhow::Result<()> {
+    args.check_options(&["out"])?;
+    let out = args.option("out").map(PathBuf::from);
+    let meta = repair_file(&args.file, out.as_deref())?;
+    let path = out.as_ref().unwrap_or(&args.file);
+    println!(
+        "rewrote footer of {} with {} columns",
+        path.display(),
+        meta.column_meta.len()
+    );
+    Ok(())
+}
+fn from_csv(args: &Args) -> anyhow::Result<()> {
+    args.check_options(&[
+        "out",
+        "schema",
+        "block-size",
+        "table",
+        "sample-rows",
+        "delimiter",
+        "no-header",
+        "codec",
+        "encode",
+        "var-str",
+        "bloom",
+    ])?;
+    let Some(out) = args.option("out") else {
+        bail!("from-csv needs --out");
+    };
+    let mut options = CsvImportOptions {
+        table_name: args.option("table").map(|table| table.to_string()),
+        ..Default::default()
+    };
+    if let Some(schema) = args.option("schema") {
+        options.columns = Some(parse_schema(schema)?);
+    }
+    if let Some(block_size) = args.option("block-size") {
+        options.max_values_per_block = block_size.parse().context("invalid --block-size")?;
+    }
+    if let Some(sample_rows) = args.option("sample-rows") {
+        options.sample_rows = sample_rows.parse().context("invalid --sample-rows")?;
+    }
+    if let Some(delimiter) = args.option("delimiter") {
+        let [delimiter] = delimiter.as_bytes() else {
+            bail!("--delimiter should be a single byte");
+        };
+        options.delimiter = *delimiter;
+    }
+    if let Some(no_header) = args.option("no-header") {
+        options.has_header = !no_header.parse::<bool>().context("invalid --no-header")?;
+    }
+    if let Some(codec) = args.option("codec") {
+        options.codec = Codec::from_name(codec)?;
+    }
+    if let Some(encode) = args.option("encode") {
+        options.auto_encoding = encode.parse().context("invalid --encode")?;
+    }
+    if let Some(var_str) = args.option("var-str") {
+        options.var_str = var_str.parse().context("invalid --var-str")?;
+    }
+    if let Some(bloom) = args.option("bloom") {
+        options.bloom_columns = bloom
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect();
+    }
+    let row_count = import_csv(&args.file, PathBuf::from(out), &options)?;
+    println!("wrote {} rows to {}", row_count, out);
+    Ok(())
+}
+fn run(argv: &[String]) -> anyhow::Result<bool> {
+    let Some((command, rest)) = argv.split_first() else {
+        bail!("need a command");
+    };
+    let args = Args::parse(rest)?;
+    match command.as_str() {
+        "info" => info(&args)?,
+        "stats" => stats(&args)?,
+        "cat" => cat(&args)?,
+        "check" => return check(&args),
+        "repair" => repair(&args)?,
+        "from-csv" => from_csv(&args)?,
+        _ => bail!("unknown command {}", command),
+    }
+    Ok(true)
+}
+fn main() {
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    if argv.is_empty() || argv[0] == "-h" || argv[0] == "--help" {
+        println!("{}", USAGE);
+        return;
+    }
+    match run(&argv) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("db721: {:#}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
@@ -1,7 +1,21 @@
+pub mod check;
+pub mod csv_import;
+pub mod dataset;
+pub mod binary;
+pub mod bloom;
+pub mod datetime;
+pub mod decimal;
+pub mod encoding;
+pub mod predicate;
+pub mod repair;
+pub mod writer;
+
+use crate::db721::binary::Binary;
+use crate::db721::bloom::BloomFilter;
+use crate::db721::decimal::Decimal;
 use crate::db721::DB721Type::Str;
 use anyhow::{bail, Context};
 use bytes::Buf;
-use pgrx::pg_sys::{float8, Oid, PlannerInfo, RelOptInfo};
 use serde::{Deserialize, Serialize};
 use std::cmp::min;
 use std::collections::HashMap;
@@ -11,9 +25,16 @@ use std::os::unix::fs::{FileExt, MetadataExt};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+/// 长时间解码时定期调用的中断检查，由调用方（如FDW）提供
+pub type InterruptCheck = fn();
+/// 批量解码时每解码这么多个值检查一次中断
+const INTERRUPT_CHECK_INTERVAL: usize = 8192;
+
 pub struct Block {
     meta: BlockMeta,
     data: Vec<u8>,
+    /// 从数据开头拆出的validity bitmap，block中没有NULL时为空
+    validity: Vec<u8>,
 }
 impl<'a> Block {
     pub fn serialize_ref(&'a self) -> &'a [u8] {
@@ -22,13 +43,197 @@ impl<'a> Block {
     pub fn serialize_clone(&self) -> Vec<u8> {
         self.data.clone()
     }
+    /// 直接借用block中第idx个定长字符串，并去掉末尾填充的'\0'
+    pub fn str_bytes(&'a self, idx: usize) -> &'a [u8] {
+        let chunk = &self.data[idx * 32..(idx + 1) * 32];
+        let len = chunk.iter().position(|b| *b == 0).unwrap_or(32);
+        &chunk[..len]
+    }
+    pub fn str_at(&'a self, idx: usize) -> &'a str {
+        std::str::from_utf8(self.str_bytes(idx)).expect("need valid UTF-8 String")
+    }
 }
+/// 注意untagged反序列化按顺序尝试，JSON中的整数会先匹配为Integer、小数会匹配为Double，
+/// date和timestamp在JSON中也是整数、decimal和binary是字符串，因此block统计信息读取后需要用coerce按列类型进行转换
 #[derive(Debug, Clone, Deserialize, Serialize, PartialOrd, PartialEq)]
 #[serde(untagged)]
 pub enum DB721Type {
-    Float(f32),
     Integer(i32),
+    BigInt(i64),
+    Double(f64),
+    Float(f32),
+    Bool(bool),
     Str(String),
+    /// 自1970-01-01起的天数
+    Date(i32),
+    /// 自1970-01-01 00:00:00 UTC起的微秒数
+    Timestamp(i64),
+    Decimal(Decimal),
+    Binary(Binary),
+}
+impl DB721Type {
+    /// 将值转换为value_type对应的类型，无法无损转换时保持原样
+    pub fn coerce(self, value_type: &str) -> DB721Type {
+        if let Some((_, scale)) = decimal::params(value_type) {
+            let decimal = match &self {
+                DB721Type::Integer(val) => Some(Decimal::new(*val as i128, 0)),
+                DB721Type::BigInt(val) => Some(Decimal::new(*val as i128, 0)),
+                DB721Type::Str(val) => val.parse::<Decimal>().ok(),
+                DB721Type::Decimal(val) => Some(*val),
+                _ => None,
+            };
+            return match decimal.and_then(|decimal| decimal.rescale(scale)) {
+                Some(decimal) => DB721Type::Decimal(decimal),
+                None => self,
+            };
+        }
+        if let Some(width) = binary::params(value_type) {
+            return match &self {
+                DB721Type::Str(val) => match val.parse::<Binary>() {
+                    Ok(binary) if binary.len() == width => DB721Type::Binary(binary),
+                    _ => self,
+                },
+                _ => self,
+            };
+        }
+        match (value_type, self) {
+            ("float", DB721Type::Integer(val)) => DB721Type::Float(val as f32),
+            // footer中的float按f64解析，转换回f32得到写入时的值
+            ("float", DB721Type::Double(val)) => DB721Type::Float(val as f32),
+            ("int", DB721Type::Float(val)) if val.fract() == 0.0 => DB721Type::Integer(val as i32),
+            ("int", DB721Type::Double(val)) if val.fract() == 0.0 => DB721Type::Integer(val as i32),
+            ("bigint", DB721Type::Integer(val)) => DB721Type::BigInt(val as i64),
+            ("double", DB721Type::Integer(val)) => DB721Type::Double(val as f64),
+            ("double", DB721Type::Float(val)) => DB721Type::Double(val as f64),
+            ("date", DB721Type::Integer(val)) => DB721Type::Date(val),
+            ("timestamp", DB721Type::Integer(val)) => DB721Type::Timestamp(val as i64),
+            ("timestamp", DB721Type::BigInt(val)) => DB721Type::Timestamp(val),
+            (_, val) => val,
+        }
+    }
+    /// 值对应的列类型
+    pub fn value_type(&self) -> &'static str {
+        match self {
+            DB721Type::Integer(_) => "int",
+            DB721Type::BigInt(_) => "bigint",
+            DB721Type::Double(_) => "double",
+            DB721Type::Float(_) => "float",
+            DB721Type::Bool(_) => "bool",
+            DB721Type::Str(_) => "str",
+            DB721Type::Date(_) => "date",
+            DB721Type::Timestamp(_) => "timestamp",
+            DB721Type::Decimal(_) => "decimal",
+            DB721Type::Binary(_) => "binary",
+        }
+    }
+    /// 定长类型的小端编码，str和binary为原始字节，decimal为i128（按精度存为i64时由调用方截取）
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        match self {
+            DB721Type::Integer(val) | DB721Type::Date(val) => val.to_le_bytes().to_vec(),
+            DB721Type::BigInt(val) | DB721Type::Timestamp(val) => val.to_le_bytes().to_vec(),
+            DB721Type::Double(val) => val.to_le_bytes().to_vec(),
+            DB721Type::Float(val) => val.to_le_bytes().to_vec(),
+            DB721Type::Bool(val) => vec![*val as u8],
+            DB721Type::Str(val) => val.as_bytes().to_vec(),
+            DB721Type::Decimal(val) => val.value().to_le_bytes().to_vec(),
+            DB721Type::Binary(val) => val.as_bytes().to_vec(),
+        }
+    }
+}
+impl std::fmt::Display for DB721Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DB721Type::Integer(val) => write!(f, "{}", val),
+            DB721Type::BigInt(val) => write!(f, "{}", val),
+            DB721Type::Double(val) => write!(f, "{}", val),
+            DB721Type::Float(val) => write!(f, "{}", val),
+            DB721Type::Bool(val) => write!(f, "{}", val),
+            DB721Type::Str(val) => write!(f, "{}", val),
+            DB721Type::Date(val) => write!(f, "{}", datetime::format_date(*val)),
+            DB721Type::Timestamp(val) => write!(f, "{}", datetime::format_timestamp(*val)),
+            DB721Type::Decimal(val) => write!(f, "{}", val),
+            DB721Type::Binary(val) => write!(f, "{}", val),
+        }
+    }
+}
+/// 每个值占用的字节数，不支持的类型为None
+pub fn value_width(value_type: &str) -> Option<usize> {
+    match value_type {
+        "bool" => Some(1),
+        "int" | "float" | "date" => Some(4),
+        "bigint" | "double" | "timestamp" => Some(8),
+        "str" => Some(32),
+        _ => decimal::params(value_type)
+            .map(|(precision, _)| decimal::width(precision))
+            .or_else(|| binary::params(value_type)),
+    }
+}
+/// block数据的压缩方式，由列的codec声明，没有codec的列不压缩
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    None,
+    Lz4,
+}
+impl Codec {
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "none" => Ok(Codec::None),
+            "lz4" => Ok(Codec::Lz4),
+            _ => bail!("no support for codec = {}", name),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Lz4 => "lz4",
+        }
+    }
+}
+/// 单个block的编码方式，记录在block统计信息的encoding中，没有encoding的block为plain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// 定长的原始值
+    Plain,
+    /// 只用于str：block统计信息中的dictionary加上每个值在字典中的下标，
+    /// 字典不超过256项时下标占1字节，否则占2字节
+    Dict,
+    /// 只用于int：连续相同的值合并为一个run，见encoding模块
+    Rle,
+    /// 只用于int：按组存放delta-of-delta，见encoding模块
+    Delta,
+    /// 只用于str：偏移加数据的变长字符串，不受32字节的限制
+    Var,
+}
+impl Encoding {
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "plain" => Ok(Encoding::Plain),
+            "dict" => Ok(Encoding::Dict),
+            "rle" => Ok(Encoding::Rle),
+            "delta" => Ok(Encoding::Delta),
+            "var" => Ok(Encoding::Var),
+            _ => bail!("no support for encoding = {}", name),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Plain => "plain",
+            Encoding::Dict => "dict",
+            Encoding::Rle => "rle",
+            Encoding::Delta => "delta",
+            Encoding::Var => "var",
+        }
+    }
+}
+/// 字典的最大项数，下标最多占2字节
+pub const DICT_MAX_SIZE: usize = 1 << 16;
+/// 字典下标占用的字节数
+pub fn dict_code_width(dict_size: usize) -> usize {
+    if dict_size <= 1 << 8 {
+        1
+    } else {
+        2
+    }
 }
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BlockMeta {
@@ -36,30 +241,233 @@ pub struct BlockMeta {
     value_num: i32,
     min: DB721Type,
     max: DB721Type,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     min_len: Option<i32>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     max_len: Option<i32>,
+    /// 压缩后在文件中占用的字节数，只有压缩的列才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compressed_size: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+    /// dict编码的block中所有不同的取值，值在数据中以下标表示
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dictionary: Option<Vec<String>>,
+    /// rle、delta和var编码后的字节数，其余编码的大小由num计算
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoded_size: Option<i32>,
+    /// NULL的个数，大于0时block数据开头有validity bitmap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    null_count: Option<i32>,
+    /// float、double的block中有NaN，NaN不计入min/max
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    has_nan: Option<bool>,
+    /// 不是NULL的值的bloom filter，只有写入时指定的列才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bloom: Option<BloomFilter>,
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ColumnMeta {
     #[serde(rename = "type")]
-    value_type: String,
+    base_type: String,
     start_offset: i32,
     num_blocks: i32,
     #[serde(rename = "block_stats")]
     block_meta: HashMap<String, BlockMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    codec: Option<String>,
+    /// 只用于decimal列
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    precision: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scale: Option<i32>,
+    /// 只用于binary列
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<i32>,
+    /// value_type()的结果，构造或读取footer时计算一次
+    #[serde(skip)]
+    full_value_type: String,
+}
+impl BlockMeta {
+    pub fn value_num(&self) -> i32 {
+        self.value_num
+    }
+    pub fn min(&self) -> &DB721Type {
+        &self.min
+    }
+    pub fn max(&self) -> &DB721Type {
+        &self.max
+    }
+    pub fn min_len(&self) -> Option<i32> {
+        self.min_len
+    }
+    pub fn max_len(&self) -> Option<i32> {
+        self.max_len
+    }
+    pub fn compressed_size(&self) -> Option<i32> {
+        self.compressed_size
+    }
+    /// encoding在DB721::open时已经检查过
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.as_deref().map_or(Encoding::Plain, |name| {
+            Encoding::from_name(name).expect("encoding should be checked when opening the file")
+        })
+    }
+    pub fn dictionary(&self) -> Option<&[String]> {
+        self.dictionary.as_deref()
+    }
+    pub fn null_count(&self) -> i32 {
+        self.null_count.unwrap_or(0)
+    }
+    pub fn has_nan(&self) -> bool {
+        self.has_nan.unwrap_or(false)
+    }
+    pub fn bloom(&self) -> Option<&BloomFilter> {
+        self.bloom.as_ref()
+    }
+    /// 所有值都是NULL，此时min/max没有意义
+    pub fn all_null(&self) -> bool {
+        self.value_num > 0 && self.null_count() == self.value_num
+    }
+    /// validity bitmap的字节数，每行1位，置位表示该行不是NULL
+    pub fn validity_size(&self) -> usize {
+        if self.null_count() > 0 {
+            (self.value_num as usize).div_ceil(8)
+        } else {
+            0
+        }
+    }
+    /// 编码后（压缩前）的字节数，包括validity bitmap
+    pub fn encoded_size(&self, value_type: &str) -> usize {
+        self.validity_size() + self.values_size(value_type)
+    }
+    /// 编码后的值占用的字节数
+    fn values_size(&self, value_type: &str) -> usize {
+        let width = match self.encoding() {
+            Encoding::Plain => value_width(value_type).expect("unsupported value type"),
+            Encoding::Dict => {
+                dict_code_width(self.dictionary().map_or(0, |dictionary| dictionary.len()))
+            }
+            Encoding::Rle | Encoding::Delta | Encoding::Var => {
+                return self.encoded_size.unwrap_or(0) as usize
+            }
+        };
+        width * self.value_num as usize
+    }
+    /// 检查encoding及其附带的信息是否与列的类型一致，null_count是否在合理范围内
+    fn check_layout(&self, value_type: &str) -> anyhow::Result<()> {
+        if self.null_count() < 0 || self.null_count() > self.value_num {
+            bail!("null_count {} is out of range", self.null_count());
+        }
+        let encoding = match &self.encoding {
+            Some(encoding) => Encoding::from_name(encoding)?,
+            None => Encoding::Plain,
+        };
+        let encoding_type = match encoding {
+            Encoding::Plain => value_type,
+            Encoding::Dict | Encoding::Var => "str",
+            Encoding::Rle | Encoding::Delta => "int",
+        };
+        if encoding_type != value_type {
+            bail!("{} encoding needs a {} column", encoding.name(), encoding_type);
+        }
+        if encoding == Encoding::Dict {
+            let Some(dictionary) = &self.dictionary else {
+                bail!("dict encoding needs a dictionary");
+            };
+            if dictionary.len() > DICT_MAX_SIZE {
+                bail!("dictionary has more than {} values", DICT_MAX_SIZE);
+            }
+        }
+        if matches!(encoding, Encoding::Rle | Encoding::Delta | Encoding::Var) {
+            match self.encoded_size {
+                Some(encoded_size) if encoded_size >= 0 => {}
+                _ => bail!("{} encoding needs a non-negative encoded_size", encoding.name()),
+            }
+        }
+        if let Some(bloom) = &self.bloom {
+            if !bloom::supports(value_type) {
+                bail!("bloom filter is not supported for {} columns", value_type);
+            }
+            if bloom.size() == 0 || bloom.hashes() == 0 || bloom.hashes() > bloom::MAX_HASHES {
+                bail!("bloom filter should have bits and 1 to {} hashes", bloom::MAX_HASHES);
+            }
+        }
+        Ok(())
+    }
 }
 impl ColumnMeta {
+    /// value_type中的参数拆分到footer的precision、scale和width中
+    pub(crate) fn new(
+        value_type: &str,
+        start_offset: i32,
+        block_meta: HashMap<String, BlockMeta>,
+        codec: Option<String>,
+    ) -> Self {
+        let mut column_meta = Self {
+            base_type: value_type.to_string(),
+            start_offset,
+            num_blocks: block_meta.len() as i32,
+            block_meta,
+            codec,
+            precision: None,
+            scale: None,
+            width: None,
+            full_value_type: String::new(),
+        };
+        if let Some((precision, scale)) = decimal::params(value_type) {
+            column_meta.base_type = String::from("decimal");
+            column_meta.precision = Some(precision as i32);
+            column_meta.scale = Some(scale as i32);
+        } else if let Some(width) = binary::params(value_type) {
+            column_meta.base_type = String::from("binary");
+            column_meta.width = Some(width as i32);
+        }
+        column_meta.full_value_type = column_meta.resolve_value_type();
+        column_meta
+    }
+    /// 列的类型，decimal列带上精度和小数位数，如 decimal(12,2)，binary列带上宽度，如 binary(16)
+    pub fn value_type(&self) -> &str {
+        &self.full_value_type
+    }
+    fn resolve_value_type(&self) -> String {
+        match (self.base_type.as_str(), self.precision, self.scale) {
+            ("decimal", Some(precision), scale) if precision > 0 && scale.unwrap_or(0) >= 0 => {
+                decimal::type_name(precision as u32, scale.unwrap_or(0) as u32)
+            }
+            ("binary", _, _) if self.width.unwrap_or(0) > 0 => {
+                binary::type_name(self.width.unwrap() as usize)
+            }
+            _ => self.base_type.clone(),
+        }
+    }
+    pub fn start_offset(&self) -> i32 {
+        self.start_offset
+    }
+    pub fn num_blocks(&self) -> i32 {
+        self.num_blocks
+    }
+    pub fn block_meta(&self, block_idx: i32) -> Option<&BlockMeta> {
+        self.block_meta.get(&block_idx.to_string())
+    }
+    /// codec在DB721::open时已经检查过
+    pub fn codec(&self) -> Codec {
+        self.codec.as_deref().map_or(Codec::None, |name| {
+            Codec::from_name(name).expect("codec should be checked when opening the file")
+        })
+    }
+    /// block在文件中占用的字节数
+    pub fn stored_size(&self, blk_meta: &BlockMeta) -> usize {
+        match (self.codec(), blk_meta.compressed_size) {
+            (Codec::Lz4, Some(compressed_size)) => compressed_size as usize,
+            _ => blk_meta.encoded_size(self.value_type()),
+        }
+    }
     pub fn get_offset_of_block(&self, block_idx: i32) -> usize {
         let mut offset = 0usize;
         for i in 0..block_idx {
             let blk_meta = self.block_meta.get(&i.to_string()).unwrap();
-            offset += match self.value_type.as_str() {
-                "int" | "float" => 4usize,
-                "str" => 32usize,
-                _ => panic!("unsupported value type"),
-            } * blk_meta.value_num as usize;
+            offset += self.stored_size(blk_meta);
         }
         // dbg!(offset);
         offset
@@ -74,11 +482,27 @@ pub struct DB721Meta {
     #[serde(rename = "Columns")]
     pub column_meta: HashMap<String, ColumnMeta>,
 }
+impl DB721Meta {
+    pub fn table_name(&self) -> &str {
+        self.table_name.as_str()
+    }
+    pub fn max_value_per_block(&self) -> i32 {
+        self.max_value_per_block
+    }
+    /// 按列在文件中的存储顺序（start_offset）排列的列
+    pub fn sorted_columns(&self) -> Vec<(&String, &ColumnMeta)> {
+        let mut columns: Vec<_> = self.column_meta.iter().collect();
+        columns.sort_by_key(|(_, column_meta)| column_meta.start_offset);
+        columns
+    }
+}
 #[derive(Debug, Clone)]
 pub struct DB721 {
     pub path: PathBuf,
     pub meta: DB721Meta,
     meta_size: i32,
+    /// 从hive风格的目录名（如 date=2023-01-01）中解析出的分区列取值
+    pub partition_values: HashMap<String, String>,
 }
 impl DB721 {
     pub fn open(path: PathBuf) -> anyhow::Result<Self> {
@@ -96,11 +520,26 @@ impl DB721 {
         r_size = file.read_to_end(&mut buf)?;
         assert_eq!(r_size, meta_size as usize + 4);
         assert_eq!(buf[0], b'{');
-        let db721_meta: DB721Meta = serde_json::from_slice(&buf[0..meta_size as usize])?;
+        let mut db721_meta: DB721Meta = serde_json::from_slice(&buf[0..meta_size as usize])?;
+        for (column_name, column_meta) in db721_meta.column_meta.iter_mut() {
+            if let Some(codec) = &column_meta.codec {
+                Codec::from_name(codec).with_context(|| format!("column {}", column_name))?;
+            }
+            column_meta.full_value_type = column_meta.resolve_value_type();
+            let value_type = column_meta.full_value_type.as_str();
+            for (block_idx, blk_meta) in column_meta.block_meta.iter_mut() {
+                blk_meta
+                    .check_layout(value_type)
+                    .with_context(|| format!("column {} block {}", column_name, block_idx))?;
+                blk_meta.min = blk_meta.min.clone().coerce(value_type);
+                blk_meta.max = blk_meta.max.clone().coerce(value_type);
+            }
+        }
         return Ok(Self {
             path,
             meta: db721_meta,
             meta_size,
+            partition_values: HashMap::new(),
         });
     }
     pub fn row_count(&self) -> usize {
@@ -114,67 +553,312 @@ impl DB721 {
         }
         row_cnt
     }
+    /// 给定block中的行数
+    pub fn row_count_of_blocks(&self, block_indices: &[i32]) -> usize {
+        let Some(column_meta) = self.meta.column_meta.values().next() else {
+            return 0;
+        };
+        block_indices
+            .iter()
+            .filter_map(|block_idx| column_meta.block_meta.get(&block_idx.to_string()))
+            .map(|block_meta| block_meta.value_num as usize)
+            .sum()
+    }
 }
-pub struct BlockIterator {
-    block: Arc<Block>,
-    value_type: String,
-    offset: usize,
-    next_value_idx: i32,
-    block_meta: BlockMeta,
+/// 一个block解码后的列式缓冲区，避免逐个值构造DB721Type
+#[derive(Clone)]
+pub struct BlockBatch {
+    values: BatchValues,
+    /// 每行是否不是NULL，block中没有NULL时为空
+    validity: Vec<bool>,
 }
-impl BlockIterator {
-    pub fn new(block: Arc<Block>, value_type: String, meta: BlockMeta) -> anyhow::Result<Self> {
-        match value_type.as_str() {
-            "int" | "float" | "str" => {}
+#[derive(Clone)]
+pub enum BatchValues {
+    Integer(Vec<i32>),
+    BigInt(Vec<i64>),
+    Double(Vec<f64>),
+    Float(Vec<f32>),
+    Bool(Vec<bool>),
+    /// 缩放后的整数及列的scale
+    Decimal(Vec<i128>, u32),
+    /// 从第一个需要的值开始的数据及每个值的宽度
+    Binary(Vec<u8>, usize),
+    /// 自1970-01-01起的天数
+    Date(Vec<i32>),
+    /// 自1970-01-01 00:00:00 UTC起的微秒数
+    Timestamp(Vec<i64>),
+    /// 字符串不做拷贝，访问时直接从block的数据中借用
+    Str(Arc<Block>),
+    /// dict编码的字符串，访问时从block统计信息的字典中借用
+    Dict(Arc<Block>, Vec<u16>),
+    /// var编码的字符串，第idx个值为block数据中ranges[idx]到ranges[idx + 1]之间的字节
+    Var(Arc<Block>, Vec<usize>),
+}
+impl BlockBatch {
+    pub fn decode(block: Arc<Block>, value_type: &str) -> anyhow::Result<Self> {
+        Self::decode_with_interrupt(block, value_type, None)
+    }
+    pub fn decode_with_interrupt(
+        block: Arc<Block>,
+        value_type: &str,
+        interrupt_check: Option<InterruptCheck>,
+    ) -> anyhow::Result<Self> {
+        Self::decode_from(block, value_type, 0, interrupt_check)
+    }
+    /// 只解码block中第start行及之后的值，返回的batch从第start行开始
+    pub fn decode_from(
+        block: Arc<Block>,
+        value_type: &str,
+        start: usize,
+        interrupt_check: Option<InterruptCheck>,
+    ) -> anyhow::Result<Self> {
+        let value_num = block.meta.value_num as usize;
+        let start = start.min(value_num);
+        let validity = if block.validity.is_empty() {
+            Vec::new()
+        } else {
+            (start..value_num)
+                .map(|idx| block.validity[idx / 8] & (1 << (idx % 8)) != 0)
+                .collect()
+        };
+        let decimal_params = decimal::params(value_type);
+        let values = match (value_type, block.meta.encoding()) {
+            ("int", Encoding::Rle) => BatchValues::Integer(encoding::rle_decode_from(
+                &block.data,
+                value_num,
+                start,
+                interrupt_check,
+            )?),
+            ("int", Encoding::Delta) => BatchValues::Integer(encoding::delta_decode_from(
+                &block.data,
+                value_num,
+                start,
+                interrupt_check,
+            )?),
+            ("int", _) => BatchValues::Integer(decode_fixed_width(
+                &block.data[start * 4..value_num * 4],
+                interrupt_check,
+                |mut chunk| chunk.get_i32_le(),
+            )),
+            ("float", _) => BatchValues::Float(decode_fixed_width(
+                &block.data[start * 4..value_num * 4],
+                interrupt_check,
+                |mut chunk| chunk.get_f32_le(),
+            )),
+            ("bigint", _) => BatchValues::BigInt(decode_fixed_width(
+                &block.data[start * 8..value_num * 8],
+                interrupt_check,
+                |mut chunk| chunk.get_i64_le(),
+            )),
+            ("double", _) => BatchValues::Double(decode_fixed_width(
+                &block.data[start * 8..value_num * 8],
+                interrupt_check,
+                |mut chunk| chunk.get_f64_le(),
+            )),
+            ("bool", _) => BatchValues::Bool(decode_fixed_width(
+                &block.data[start..value_num],
+                interrupt_check,
+                |chunk| chunk[0] != 0,
+            )),
+            ("date", _) => BatchValues::Date(decode_fixed_width(
+                &block.data[start * 4..value_num * 4],
+                interrupt_check,
+                |mut chunk| chunk.get_i32_le(),
+            )),
+            ("timestamp", _) => BatchValues::Timestamp(decode_fixed_width(
+                &block.data[start * 8..value_num * 8],
+                interrupt_check,
+                |mut chunk| chunk.get_i64_le(),
+            )),
+            _ if decimal_params.is_some() => {
+                let (precision, scale) = decimal_params.unwrap();
+                let values = if decimal::width(precision) == 8 {
+                    decode_fixed_width(
+                        &block.data[start * 8..value_num * 8],
+                        interrupt_check,
+                        |mut chunk| chunk.get_i64_le(),
+                    )
+                    .into_iter()
+                    .map(|value| value as i128)
+                    .collect()
+                } else {
+                    decode_fixed_width(
+                        &block.data[start * 16..value_num * 16],
+                        interrupt_check,
+                        |mut chunk| chunk.get_i128_le(),
+                    )
+                };
+                BatchValues::Decimal(values, scale)
+            }
+            _ if binary::params(value_type).is_some() => {
+                let width = binary::params(value_type).unwrap();
+                if block.data.len() < value_num * width {
+                    bail!("binary block is shorter than {} values", value_num);
+                }
+                let data = block.data[start * width..value_num * width].to_vec();
+                BatchValues::Binary(data, width)
+            }
+            ("str", Encoding::Dict) => {
+                let dict_size = block.meta.dictionary().map_or(0, |dictionary| dictionary.len());
+                let code_width = dict_code_width(dict_size);
+                let data = &block.data[start * code_width..value_num * code_width];
+                let codes = if code_width == 1 {
+                    data.iter().map(|code| *code as u16).collect()
+                } else {
+                    decode_fixed_width(data, interrupt_check, |mut chunk| chunk.get_u16_le())
+                };
+                if let Some(code) = codes.iter().find(|code| **code as usize >= dict_size) {
+                    bail!("dictionary code {} is out of range", code);
+                }
+                BatchValues::Dict(block, codes)
+            }
+            ("str", Encoding::Var) => {
+                let ranges =
+                    encoding::var_ranges_from(&block.data, value_num, start, interrupt_check)?;
+                BatchValues::Var(block, ranges)
+            }
+            ("str", _) => {
+                if block.data.len() < value_num * 32 {
+                    bail!("str block is shorter than {} values", value_num);
+                }
+                if start == 0 {
+                    BatchValues::Str(block)
+                } else {
+                    // 字符串按引用访问，跳过开头的行时只保留之后的数据
+                    let mut meta = block.meta.clone();
+                    meta.value_num = (value_num - start) as i32;
+                    BatchValues::Str(Arc::new(Block {
+                        meta,
+                        data: block.data[start * 32..value_num * 32].to_vec(),
+                        validity: Vec::new(),
+                    }))
+                }
+            }
             _ => bail!(format!("no support for value type = {}", value_type)),
         };
-        Ok(Self {
-            block,
-            value_type,
-            offset: 0,
-            block_meta: meta,
-            next_value_idx: 1,
-        })
+        Ok(Self { values, validity })
+    }
+    pub fn values(&self) -> &BatchValues {
+        &self.values
+    }
+    pub fn len(&self) -> usize {
+        match &self.values {
+            BatchValues::Integer(values) | BatchValues::Date(values) => values.len(),
+            BatchValues::BigInt(values) | BatchValues::Timestamp(values) => values.len(),
+            BatchValues::Double(values) => values.len(),
+            BatchValues::Float(values) => values.len(),
+            BatchValues::Bool(values) => values.len(),
+            BatchValues::Decimal(values, _) => values.len(),
+            BatchValues::Binary(data, width) => data.len() / width,
+            BatchValues::Str(block) => block.meta.value_num as usize,
+            BatchValues::Dict(_, codes) => codes.len(),
+            BatchValues::Var(_, ranges) => ranges.len() - 1,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// NULL行在values中为占位值（0、false或空字符串）
+    pub fn is_null(&self, idx: usize) -> bool {
+        !self.validity.is_empty() && !self.validity[idx]
+    }
+    pub fn ints(&self) -> Option<&[i32]> {
+        match &self.values {
+            BatchValues::Integer(values) => Some(values.as_slice()),
+            _ => None,
+        }
+    }
+    pub fn floats(&self) -> Option<&[f32]> {
+        match &self.values {
+            BatchValues::Float(values) => Some(values.as_slice()),
+            _ => None,
+        }
+    }
+    /// 第idx个字符串的字节，调用方需保证该batch为字符串
+    pub fn str_bytes(&self, idx: usize) -> &[u8] {
+        match &self.values {
+            BatchValues::Str(block) => block.str_bytes(idx),
+            BatchValues::Dict(block, codes) => {
+                block.meta.dictionary().unwrap()[codes[idx] as usize].as_bytes()
+            }
+            BatchValues::Var(block, ranges) => &block.data[ranges[idx]..ranges[idx + 1]],
+            _ => panic!("str_bytes on non-str batch"),
+        }
+    }
+    pub fn str_at(&self, idx: usize) -> &str {
+        std::str::from_utf8(self.str_bytes(idx)).expect("need valid UTF-8 String")
+    }
+    /// 第idx个定长二进制值，调用方需保证该batch为binary
+    pub fn binary_bytes(&self, idx: usize) -> &[u8] {
+        match &self.values {
+            BatchValues::Binary(data, width) => &data[idx * width..(idx + 1) * width],
+            _ => panic!("binary_bytes on non-binary batch"),
+        }
+    }
+    /// 第idx个值，NULL时返回占位值，需要区分NULL时使用value
+    pub fn get(&self, idx: usize) -> DB721Type {
+        match &self.values {
+            BatchValues::Integer(values) => DB721Type::Integer(values[idx]),
+            BatchValues::BigInt(values) => DB721Type::BigInt(values[idx]),
+            BatchValues::Double(values) => DB721Type::Double(values[idx]),
+            BatchValues::Float(values) => DB721Type::Float(values[idx]),
+            BatchValues::Bool(values) => DB721Type::Bool(values[idx]),
+            BatchValues::Date(values) => DB721Type::Date(values[idx]),
+            BatchValues::Timestamp(values) => DB721Type::Timestamp(values[idx]),
+            BatchValues::Decimal(values, scale) => {
+                DB721Type::Decimal(Decimal::new(values[idx], *scale))
+            }
+            BatchValues::Binary(..) => {
+                DB721Type::Binary(Binary::new(self.binary_bytes(idx).to_vec()))
+            }
+            BatchValues::Str(_) | BatchValues::Dict(..) | BatchValues::Var(..) => {
+                DB721Type::Str(self.str_at(idx).to_string())
+            }
+        }
+    }
+    pub fn value(&self, idx: usize) -> Option<DB721Type> {
+        if self.is_null(idx) {
+            None
+        } else {
+            Some(self.get(idx))
+        }
+    }
+}
+fn decode_fixed_width<T>(
+    data: &[u8],
+    interrupt_check: Option<InterruptCheck>,
+    decode_one: impl Fn(&[u8]) -> T,
+) -> Vec<T> {
+    let width = std::mem::size_of::<T>();
+    let mut values = Vec::with_capacity(data.len() / width);
+    for part in data.chunks(INTERRUPT_CHECK_INTERVAL * width) {
+        if let Some(check) = interrupt_check {
+            check();
+        }
+        values.extend(part.chunks_exact(width).map(&decode_one));
+    }
+    values
+}
+/// 在BlockBatch之上逐个产出DB721Type，仅作为便捷接口
+pub struct BlockIterator {
+    batch: BlockBatch,
+    next_value_idx: usize,
+}
+impl BlockIterator {
+    pub fn new(block: Arc<Block>, value_type: String, _meta: BlockMeta) -> anyhow::Result<Self> {
+        Ok(Self::from_batch(BlockBatch::decode(block, value_type.as_str())?))
+    }
+    pub fn from_batch(batch: BlockBatch) -> Self {
+        Self {
+            batch,
+            next_value_idx: 0,
+        }
     }
     pub fn next(&mut self) -> Option<DB721Type> {
-        let mut res: DB721Type;
-        if self.next_value_idx > self.block_meta.value_num {
+        if self.next_value_idx >= self.batch.len() {
             return None;
         }
-        match self.value_type.as_str() {
-            "int" => {
-                res = DB721Type::Integer(
-                    (&self.block.data[self.offset..self.offset + 4]).get_i32_le(),
-                );
-                self.offset += 4;
-            }
-            "float" => {
-                res =
-                    DB721Type::Float((&self.block.data[self.offset..self.offset + 4]).get_f32_le());
-                self.offset += 4;
-            }
-            "str" => {
-                res = DB721Type::Str({
-                    let mut str = String::from_utf8(
-                        (&self.block.data[self.offset..self.offset + 32]).to_vec(),
-                    )
-                    .expect("need valid UTF-8 String");
-                    // TODO: should remove the suffix '\0'?
-                    let idx = str.find('\0');
-                    match idx {
-                        Some(idx) => {
-                            str.truncate(idx);
-                        }
-                        None => {}
-                    };
-                    str
-                });
-                self.offset += 32;
-            }
-            _ => {
-                panic!("iterator on unsupported value type")
-            }
-        };
+        let res = self.batch.get(self.next_value_idx);
         self.next_value_idx += 1;
         Some(res)
     }
@@ -188,6 +872,8 @@ pub struct ColumnIteratorBuilder {
     maxv: Option<DB721Type>,
     min_len: Option<i32>,
     max_len: Option<i32>,
+    interrupt_check: Option<InterruptCheck>,
+    block_indices: Option<Vec<i32>>,
 }
 impl ColumnIteratorBuilder {
     pub fn new(column_meta: ColumnMeta, column_name: String, file_path: PathBuf) -> Self {
@@ -199,10 +885,12 @@ impl ColumnIteratorBuilder {
             maxv: None,
             min_len: None,
             max_len: None,
+            interrupt_check: None,
+            block_indices: None,
         }
     }
     pub fn build(&self) -> anyhow::Result<ColumnIterator> {
-        ColumnIterator::new(
+        let mut column_iterator = ColumnIterator::new(
             self.column_name.clone(),
             self.column_meta.clone(),
             self.file_path.clone(),
@@ -210,7 +898,12 @@ impl ColumnIteratorBuilder {
             None,
             None,
             None,
-        )
+        )?;
+        column_iterator.interrupt_check = self.interrupt_check;
+        if let Some(block_indices) = &self.block_indices {
+            column_iterator.block_indices = block_indices.clone();
+        }
+        Ok(column_iterator)
     }
     pub fn set_min_value(&mut self, minv: DB721Type) -> &mut Self {
         self.minv = Some(minv);
@@ -228,19 +921,32 @@ impl ColumnIteratorBuilder {
         self.max_len = Some(max_len);
         self
     }
+    /// 在每个block边界以及批量解码过程中调用，用于响应查询取消
+    pub fn set_interrupt_check(&mut self, interrupt_check: InterruptCheck) -> &mut Self {
+        self.interrupt_check = Some(interrupt_check);
+        self
+    }
+    /// 只读取给定的block（升序），用于跨列对齐地跳过被谓词排除的block
+    pub fn set_block_indices(&mut self, block_indices: Vec<i32>) -> &mut Self {
+        self.block_indices = Some(block_indices);
+        self
+    }
 }
 
 pub struct ColumnIterator {
-    next_block_idx: i32,
-    now_block_iterator: BlockIterator,
+    block_indices: Vec<i32>,
+    next_block_pos: usize,
+    now_block_iterator: Option<BlockIterator>,
     column_meta: ColumnMeta,
     column_name: String,
     file_path: PathBuf,
-    start: bool,
     minv: Option<DB721Type>,
     maxv: Option<DB721Type>,
     min_len: Option<i32>,
     max_len: Option<i32>,
+    interrupt_check: Option<InterruptCheck>,
+    /// 之后的block开头还需要跳过的行数
+    skip_rows: usize,
     is_end: bool,
 }
 impl ColumnIterator {
@@ -255,119 +961,227 @@ impl ColumnIterator {
         min_len: Option<i32>,
         max_len: Option<i32>,
     ) -> anyhow::Result<Self> {
-        let block_meta = column_meta
+        let _ = column_meta
             .block_meta
-            .get(&0.to_string()).with_context(|| "need at least one block to read")
-            ?.clone();
-        let block = Arc::new(
-            read_one_block(
-                column_meta.value_type.clone(),
-                column_meta.start_offset as usize,
-                block_meta.clone(),
-                file_path.clone(),
-            )?,
-        );
-        let block_iterator =
-            BlockIterator::new(block, column_meta.value_type.clone(), block_meta).unwrap();
+            .get(&0.to_string()).with_context(|| "need at least one block to read")?;
+        if value_width(column_meta.value_type()).is_none() {
+            bail!(format!("no support for value type = {}", column_meta.value_type()));
+        }
+        let block_indices = (0..column_meta.num_blocks).collect();
         Ok(Self {
             column_meta,
             column_name,
             file_path,
-            next_block_idx: 1,
-            start: false,
-            now_block_iterator: block_iterator,
-            minv: None,
-            maxv: None,
-            min_len: None,
-            max_len: None,
+            block_indices,
+            next_block_pos: 0,
+            now_block_iterator: None,
+            minv,
+            maxv,
+            min_len,
+            max_len,
+            interrupt_check: None,
+            skip_rows: 0,
             is_end: false,
         })
     }
-    pub fn next(&mut self) -> Option<DB721Type> {
+    /// 读取并解码下一个block，返回整个block的列式数据
+    pub fn next_batch(&mut self) -> Option<BlockBatch> {
         if self.is_end {
             return None;
         }
-        while true {
-            if self.next_block_idx > self.column_meta.num_blocks {
-                self.is_end = true;
-                return None;
-            }
-            match self.now_block_iterator.next() {
-                None => {
-                    // let offset = self.column_meta.get_offset_of_block(self.next_block_idx);
-                    if self.next_block_idx == self.column_meta.num_blocks{
-                        self.is_end = true;
-                        return None;
-                    }
-                    let blk_meta = self
-                        .column_meta
-                        .block_meta
-                        .get(&self.next_block_idx.to_string())
-                        .unwrap()
-                        .clone();
-                    if let Some(min) = self.minv.clone() {
-                        if min > blk_meta.max {
-                            self.next_block_idx += 1;
-                            continue;
-                        }
-                    }
-                    if let Some(max) = self.maxv.clone() {
-                        if max < blk_meta.min {
-                            self.next_block_idx += 1;
-                            continue;
-                        }
-                    }
-                    let offset = self.column_meta.get_offset_of_block(self.next_block_idx);
-                    let block = Arc::new(
-                        read_one_block(
-                            self.column_meta.value_type.clone(),
-                            self.column_meta.start_offset as usize + offset,
-                            blk_meta.clone(),
-                            self.file_path.clone(),
-                        )
-                        .unwrap(),
-                    );
-                    let blk_iter =
-                        BlockIterator::new(block, self.column_meta.value_type.clone(), blk_meta)
-                            .unwrap();
-                    self.now_block_iterator = blk_iter;
-                    self.next_block_idx += 1;
+        while self.next_block_pos < self.block_indices.len() {
+            if let Some(check) = self.interrupt_check {
+                check();
+            }
+            let block_idx = self.block_indices[self.next_block_pos];
+            self.next_block_pos += 1;
+            let blk_meta = self
+                .column_meta
+                .block_meta
+                .get(&block_idx.to_string())
+                .unwrap()
+                .clone();
+            if let Some(min) = self.minv.clone() {
+                if min > blk_meta.max {
+                    continue;
                 }
-                Some(val) => {
-                    return Some(val);
+            }
+            if let Some(max) = self.maxv.clone() {
+                if max < blk_meta.min {
+                    continue;
                 }
             }
+            // 需要跳过的行覆盖整个block时不读取该block
+            let value_num = blk_meta.value_num.max(0) as usize;
+            if self.skip_rows > 0 && self.skip_rows >= value_num {
+                self.skip_rows -= value_num;
+                continue;
+            }
+            let start = std::mem::take(&mut self.skip_rows);
+            let offset = self.column_meta.get_offset_of_block(block_idx);
+            let block = Arc::new(
+                read_one_block(
+                    self.column_meta.value_type(),
+                    self.column_meta.codec(),
+                    self.column_meta.start_offset as usize + offset,
+                    blk_meta,
+                    self.file_path.clone(),
+                )
+                .unwrap(),
+            );
+            return Some(
+                BlockBatch::decode_from(
+                    block,
+                    self.column_meta.value_type(),
+                    start,
+                    self.interrupt_check,
+                )
+                .unwrap(),
+            );
         }
+        self.is_end = true;
         None
     }
+    /// 跳过之后的rows行，整个被跳过的block不会被读取，
+    /// 部分跳过的block只解码需要的部分
+    pub fn skip(&mut self, rows: usize) {
+        self.skip_rows += rows;
+    }
+    pub fn next(&mut self) -> Option<DB721Type> {
+        loop {
+            if let Some(blk_iter) = &mut self.now_block_iterator {
+                if let Some(val) = blk_iter.next() {
+                    return Some(val);
+                }
+            }
+            let batch = self.next_batch()?;
+            self.now_block_iterator = Some(BlockIterator::from_batch(batch));
+        }
+    }
+}
+/// 按batch消费一个ColumnIterator，每次给出当前值所在的batch及其下标
+pub struct ColumnCursor {
+    iterator: ColumnIterator,
+    batch: Option<BlockBatch>,
+    position: usize,
+}
+impl ColumnCursor {
+    pub fn new(iterator: ColumnIterator) -> Self {
+        Self {
+            iterator,
+            batch: None,
+            position: 0,
+        }
+    }
+    pub fn is_end(&self) -> bool {
+        self.iterator.is_end()
+    }
+    pub fn advance(&mut self) -> Option<(&BlockBatch, usize)> {
+        loop {
+            let len = self.batch.as_ref().map_or(0, |batch| batch.len());
+            if self.position < len {
+                let idx = self.position;
+                self.position += 1;
+                return Some((self.batch.as_ref().unwrap(), idx));
+            }
+            self.batch = Some(self.iterator.next_batch()?);
+            self.position = 0;
+        }
+    }
+    /// 跳过之后的rows行
+    pub fn skip(&mut self, rows: usize) {
+        let remaining = self.batch.as_ref().map_or(0, |batch| batch.len()) - self.position;
+        self.position += rows.min(remaining);
+        self.iterator.skip(rows.saturating_sub(remaining));
+    }
+}
+/// 按给定的block顺序逐行前进，跟踪当前行所在的block以及它在文件中的行号。
+/// 各列按相同的max values per block切分，因此任取一列的block统计信息即可
+pub struct RowTracker {
+    /// (block下标, 该block第一行在文件中的行号, 该block的行数)
+    blocks: Vec<(i32, usize, usize)>,
+    block_pos: usize,
+    row_in_block: usize,
+}
+impl RowTracker {
+    pub fn new(db721: &DB721, block_indices: &[i32]) -> Self {
+        let mut blocks = Vec::with_capacity(block_indices.len());
+        if let Some(column_meta) = db721.meta.column_meta.values().next() {
+            // 按下标顺序累加一次，得到每个block第一行的行号及行数
+            let mut first_row = 0;
+            let block_rows: Vec<(usize, usize)> = (0..column_meta.num_blocks)
+                .map(|idx| {
+                    let value_num = column_meta
+                        .block_meta(idx)
+                        .map_or(0, |block_meta| block_meta.value_num as usize);
+                    first_row += value_num;
+                    (first_row - value_num, value_num)
+                })
+                .collect();
+            for block_idx in block_indices {
+                let block_rows = usize::try_from(*block_idx)
+                    .ok()
+                    .and_then(|idx| block_rows.get(idx));
+                if let Some(&(first_row, value_num)) = block_rows.filter(|(_, value_num)| *value_num > 0) {
+                    blocks.push((*block_idx, first_row, value_num));
+                }
+            }
+        }
+        Self {
+            blocks,
+            block_pos: 0,
+            row_in_block: 0,
+        }
+    }
+    pub fn is_end(&self) -> bool {
+        self.block_pos >= self.blocks.len()
+    }
+    /// 前进到下一行，返回 (block下标, 文件中从0开始的行号)
+    pub fn advance(&mut self) -> Option<(i32, usize)> {
+        let (block_idx, first_row, value_num) = *self.blocks.get(self.block_pos)?;
+        let row_number = first_row + self.row_in_block;
+        self.row_in_block += 1;
+        if self.row_in_block >= value_num {
+            self.block_pos += 1;
+            self.row_in_block = 0;
+        }
+        Some((block_idx, row_number))
+    }
 }
 fn read_one_block(
-    value_type: String,
-    mut offset: usize,
+    value_type: &str,
+    codec: Codec,
+    offset: usize,
     block_meta: BlockMeta,
     path: PathBuf,
 ) -> anyhow::Result<Block> {
-    let mut res: Vec<u8> = Vec::new();
-    let mut file = std::fs::OpenOptions::new().read(true).open(path)?;
-    let _ = file.seek(SeekFrom::Start(offset as u64))?;
-    let mut size;
-    match value_type.as_str() {
-        "int" | "float" => {
-            size = block_meta.value_num * 4;
-        }
-        "str" => {
-            size = block_meta.value_num * 32;
+    let file = std::fs::OpenOptions::new().read(true).open(path)?;
+    if value_width(value_type).is_none() {
+        bail!("no support for value type = {}", value_type);
+    }
+    let size = block_meta.encoded_size(value_type);
+    let mut data = match (codec, block_meta.compressed_size) {
+        (Codec::None, _) => {
+            let mut res = vec![0u8; size];
+            file.read_exact_at(&mut res, offset as u64)?;
+            res
         }
-        _ => {
-            panic!("invalid value type ");
+        (Codec::Lz4, Some(compressed_size)) => {
+            let mut compressed = vec![0u8; compressed_size as usize];
+            file.read_exact_at(&mut compressed, offset as u64)?;
+            let res = lz4::block::decompress(&compressed, Some(size as i32))?;
+            if res.len() != size {
+                bail!("lz4 block has {} bytes, expected {}", res.len(), size);
+            }
+            res
         }
+        (Codec::Lz4, None) => bail!("lz4 block needs compressed_size"),
     };
-    res.resize(size as usize, 0u8);
-    // res.clear();
-    file.read_exact_at(&mut res, offset as u64)?;
+    let validity = data.drain(..block_meta.validity_size()).collect();
     Ok(Block {
         meta: block_meta,
-        data: res,
+        data,
+        validity,
     })
 }
 #[cfg(test)]
@@ -375,8 +1189,10 @@ mod tests {
     use std::io::Write;
     use crate::db721::DB721Type::Str;
     use crate::db721::{
-        read_one_block, BlockIterator, ColumnIterator, ColumnIteratorBuilder, DB721Type, DB721,
+        read_one_block, Block, BlockBatch, BlockIterator, BlockMeta, ColumnIterator,
+        ColumnIteratorBuilder, DB721Type, RowTracker, DB721,
     };
+    use crate::db721::writer::test_util::write_test_file;
     use std::path::PathBuf;
     use std::sync::Arc;
     use serde::de::Unexpected::Option;
@@ -401,7 +1217,8 @@ mod tests {
         for (column_name, column_meta) in db721.meta.column_meta.iter() {
             let blk_meta = column_meta.block_meta.get(&0.to_string()).unwrap().clone();
             let blk = read_one_block(
-                column_meta.value_type.clone(),
+                column_meta.value_type(),
+                column_meta.codec(),
                 column_meta.start_offset as usize,
                 blk_meta.clone(),
                 db721.path.clone(),
@@ -409,7 +1226,7 @@ mod tests {
             .unwrap();
             let mut blk_it = BlockIterator::new(
                 Arc::new(blk),
-                column_meta.value_type.clone(),
+                column_meta.value_type().to_string(),
                 blk_meta.clone(),
             )
             .unwrap();
@@ -476,6 +1293,59 @@ mod tests {
         }
     }
     #[test]
+    fn test_block_batch_decode() {
+        let mut data = Vec::new();
+        for s in ["FEMALE", "MALE"] {
+            let mut buf = [0u8; 32];
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            data.extend_from_slice(&buf);
+        }
+        let block = Block {
+            meta: BlockMeta {
+                value_num: 2,
+                min: DB721Type::Str(String::from("FEMALE")),
+                max: DB721Type::Str(String::from("MALE")),
+                min_len: Some(4),
+                max_len: Some(6),
+                compressed_size: None,
+                encoding: None,
+                dictionary: None,
+                encoded_size: None,
+                null_count: None,
+                has_nan: None,
+                bloom: None,
+            },
+            data,
+            validity: Vec::new(),
+        };
+        assert_eq!(block.str_bytes(1), b"MALE");
+        let batch = BlockBatch::decode(Arc::new(block), "str").unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.str_at(0), "FEMALE");
+        assert_eq!(batch.str_at(1), "MALE");
+        let mut blk_it = BlockIterator::from_batch(batch);
+        assert_eq!(blk_it.next(), Some(DB721Type::Str(String::from("FEMALE"))));
+        assert_eq!(blk_it.next(), Some(DB721Type::Str(String::from("MALE"))));
+        assert_eq!(blk_it.next(), None);
+    }
+    #[test]
+    fn test_row_tracker() {
+        let rows = (0..10).map(|id| [Some(DB721Type::Integer(id))]);
+        let file = write_test_file("row_tracker", 4, &[("identifier", "int")], rows, |_| {});
+        let db721 = file.open();
+        // 跳过block 0，行号从block 1的第一行开始
+        let mut row_tracker = RowTracker::new(&db721, &[1, 2]);
+        let (block_idx, row_number) = row_tracker.advance().unwrap();
+        assert_eq!(block_idx, 1);
+        assert_eq!(row_number, db721.row_count_of_blocks(&[0]));
+        let mut rows = vec![(block_idx, row_number)];
+        while let Some(row) = row_tracker.advance() {
+            rows.push(row);
+        }
+        assert!(row_tracker.is_end());
+        assert_eq!(rows, vec![(1, 4), (1, 5), (1, 6), (1, 7), (2, 8), (2, 9)]);
+    }
+    #[test]
     fn test_str_truncate(){
         let mut strr = String::from("12345");
         strr.truncate(0);
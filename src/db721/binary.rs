@@ -0,0 +1,124 @@
+//! 定长二进制列，如16字节的UUID。列的类型写作 `binary(width)`，footer中为 `"type": "binary"` 加上width，
+//! 每个值按原样占用width字节，block统计信息中的min/max写为十六进制字符串，按字节比较
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+
+/// 最大宽度
+pub const MAX_WIDTH: usize = 1024;
+/// UUID的宽度
+pub const UUID_WIDTH: usize = 16;
+
+/// 按字节比较，与postgres中uuid和bytea的顺序一致
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Binary(Vec<u8>);
+impl Binary {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+impl FromStr for Binary {
+    type Err = anyhow::Error;
+    /// 接受十六进制字符串，忽略UUID中的 `-` 以及bytea输出的 `\x` 前缀，不区分大小写
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        let digits: String = value
+            .strip_prefix("\\x")
+            .unwrap_or(value)
+            .chars()
+            .filter(|c| *c != '-')
+            .collect();
+        Ok(Self(decode_hex(&digits)?))
+    }
+}
+/// 输出为小写十六进制，UUID宽度的值按 8-4-4-4-12 加上 `-`，与postgres的uuid输出一致
+impl std::fmt::Display for Binary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, b) in self.0.iter().enumerate() {
+            if self.0.len() == UUID_WIDTH && matches!(idx, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+impl Serialize for Binary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for Binary {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+/// 小写十六进制
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+/// 解析十六进制字符串，不区分大小写
+pub fn decode_hex(digits: &str) -> anyhow::Result<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        anyhow::bail!("invalid hex {}, expected an even number of digits", digits);
+    }
+    let hex_digit = |b: u8| match b {
+        b'0'..=b'9' => Ok(b - b'0'),
+        b'a'..=b'f' => Ok(b - b'a' + 10),
+        b'A'..=b'F' => Ok(b - b'A' + 10),
+        _ => Err(anyhow::anyhow!("invalid hex {}", digits)),
+    };
+    digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| Ok(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+        .collect()
+}
+/// 解析 `binary(width)`，要求 1 <= width <= MAX_WIDTH
+pub fn params(value_type: &str) -> Option<usize> {
+    let width: usize = value_type
+        .strip_prefix("binary(")?
+        .strip_suffix(')')?
+        .trim()
+        .parse()
+        .ok()?;
+    (1..=MAX_WIDTH).contains(&width).then_some(width)
+}
+pub fn type_name(width: usize) -> String {
+    format!("binary({})", width)
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::binary::{params, Binary};
+
+    #[test]
+    fn test_binary_parse_and_format() {
+        let uuid = "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11";
+        let binary = uuid.parse::<Binary>().unwrap();
+        assert_eq!(binary.len(), 16);
+        assert_eq!(binary.as_bytes()[..2], [0xa0, 0xee]);
+        assert_eq!(binary.to_string(), uuid);
+        assert_eq!(
+            "A0EEBC999C0B4EF8BB6D6BB9BD380A11".parse::<Binary>().unwrap(),
+            binary
+        );
+        assert_eq!("\\x00ff".parse::<Binary>().unwrap().to_string(), "00ff");
+        assert!("abc".parse::<Binary>().is_err());
+        assert!("zz".parse::<Binary>().is_err());
+        // 按字节比较
+        assert!("00ff".parse::<Binary>().unwrap() < "0100".parse::<Binary>().unwrap());
+        assert_eq!(params("binary(16)"), Some(16));
+        assert_eq!(params("binary(0)"), None);
+        assert_eq!(params("binary(4096)"), None);
+        assert_eq!(params("binary"), None);
+    }
+}
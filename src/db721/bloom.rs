@@ -0,0 +1,123 @@
+//! block级别的bloom filter，用于 `col = const` 和 `col IN (...)` 跳过min/max无法排除的block。
+//! 写在footer的block统计信息中：`"bloom": {"hashes": 7, "bits": "<十六进制>"}`，
+//! 位数为bits的字节数乘8，第i个哈希的位置为 `(h1 + i * h2) % 位数`，h1为值的FNV-1a哈希
+use crate::db721::binary::{self, decode_hex, encode_hex};
+use crate::db721::{decimal, DB721Type};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// 每个不同取值占用的位数，配合7个哈希时误判率约为1%
+const BITS_PER_VALUE: usize = 10;
+const HASHES: u32 = 7;
+/// 哈希个数的上限，超过时视为footer损坏
+pub const MAX_HASHES: u32 = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    hashes: u32,
+    #[serde(
+        serialize_with = "serialize_bits",
+        deserialize_with = "deserialize_bits"
+    )]
+    bits: Vec<u8>,
+}
+fn serialize_bits<S: Serializer>(bits: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode_hex(bits))
+}
+fn deserialize_bits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let bits = String::deserialize(deserializer)?;
+    decode_hex(&bits).map_err(serde::de::Error::custom)
+}
+impl BloomFilter {
+    /// 按不同取值的个数确定大小，位数向上取整到64的倍数
+    pub fn with_capacity(distinct: usize) -> Self {
+        let bits = (distinct.max(1) * BITS_PER_VALUE).div_ceil(64) * 64;
+        Self {
+            hashes: HASHES,
+            bits: vec![0; bits / 8],
+        }
+    }
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+    /// 位数组的字节数
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+    pub fn insert_hash(&mut self, hash: u64) {
+        for pos in self.positions(hash) {
+            self.bits[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+    /// 返回false时一定不包含，返回true时可能包含
+    pub fn may_contain_hash(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+    fn positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 8;
+        let h2 = mix(hash) | 1;
+        (0..self.hashes as u64)
+            .map(move |idx| (hash.wrapping_add(idx.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
+/// 64位FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+/// splitmix64的混合函数，由h1得到第二个哈希
+fn mix(hash: u64) -> u64 {
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+/// 可以使用bloom filter的列类型。float、double的0.0和-0.0相等但编码不同，bool取值太少，均不支持
+pub fn supports(value_type: &str) -> bool {
+    matches!(value_type, "int" | "bigint" | "date" | "timestamp" | "str")
+        || decimal::params(value_type).is_some()
+        || binary::params(value_type).is_some()
+}
+/// 值的哈希，按文件中存储的编码计算：整数类为小端，str和binary为原始字节，decimal为按列的scale缩放后的i128。
+/// 不支持的类型返回None
+pub fn hash_value(value: &DB721Type) -> Option<u64> {
+    match value {
+        DB721Type::Integer(_)
+        | DB721Type::BigInt(_)
+        | DB721Type::Date(_)
+        | DB721Type::Timestamp(_)
+        | DB721Type::Str(_)
+        | DB721Type::Decimal(_)
+        | DB721Type::Binary(_) => Some(fnv1a(&value.to_le_bytes())),
+        DB721Type::Float(_) | DB721Type::Double(_) | DB721Type::Bool(_) => None,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::bloom::{hash_value, BloomFilter};
+    use crate::db721::DB721Type;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut bloom = BloomFilter::with_capacity(1000);
+        assert_eq!(bloom.size(), 1256);
+        for idx in 0..1000 {
+            bloom.insert_hash(hash_value(&DB721Type::Integer(idx * 2)).unwrap());
+        }
+        for idx in 0..1000 {
+            assert!(bloom.may_contain_hash(hash_value(&DB721Type::Integer(idx * 2)).unwrap()));
+        }
+        // 奇数都不在其中，误判率应当在1%左右
+        let false_positives = (0..1000)
+            .filter(|idx| {
+                bloom.may_contain_hash(hash_value(&DB721Type::Integer(idx * 2 + 1)).unwrap())
+            })
+            .count();
+        assert!(false_positives < 30, "{} false positives", false_positives);
+        assert_eq!(hash_value(&DB721Type::Double(1.0)), None);
+
+        let json = serde_json::to_string(&bloom).unwrap();
+        assert!(json.starts_with("{\"hashes\":7,\"bits\":\""));
+        assert_eq!(serde_json::from_str::<BloomFilter>(&json).unwrap(), bloom);
+    }
+}
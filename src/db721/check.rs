@@ -0,0 +1,438 @@
+//! 检查db721文件的完整性：footer与数据是否一致、block是否越界、统计信息是否正确
+use crate::db721::bloom::{self, BloomFilter};
+use crate::db721::writer::BlockStats;
+use crate::db721::{
+    read_one_block, value_width, BatchValues, BlockBatch, BlockMeta, Codec, ColumnMeta, DB721Type,
+    DB721,
+};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 检查发现的一个问题，column_name / block_idx 为空表示针对整个文件或整列
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub column_name: Option<String>,
+    pub block_idx: Option<i32>,
+    pub message: String,
+}
+impl Finding {
+    fn file(message: String) -> Self {
+        Self {
+            column_name: None,
+            block_idx: None,
+            message,
+        }
+    }
+    fn column(column_name: &str, message: String) -> Self {
+        Self {
+            column_name: Some(column_name.to_string()),
+            block_idx: None,
+            message,
+        }
+    }
+    fn block(column_name: &str, block_idx: i32, message: String) -> Self {
+        Self {
+            column_name: Some(column_name.to_string()),
+            block_idx: Some(block_idx),
+            message,
+        }
+    }
+}
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(column_name) = &self.column_name {
+            write!(f, "column {}: ", column_name)?;
+        }
+        if let Some(block_idx) = self.block_idx {
+            write!(f, "block {}: ", block_idx)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+/// 读取一个block并根据实际数据重新计算统计信息，同时返回非UTF-8字符串的个数。
+/// 只用到block_meta中的num、compressed_size、编码信息和bloom filter，
+/// rebuild_bloom为true时按实际数据重新生成bloom filter
+pub(crate) fn scan_block(
+    path: &Path,
+    value_type: &str,
+    codec: Codec,
+    offset: usize,
+    block_meta: &BlockMeta,
+    rebuild_bloom: bool,
+) -> anyhow::Result<(BlockMeta, usize)> {
+    let block = read_one_block(
+        value_type,
+        codec,
+        offset,
+        block_meta.clone(),
+        path.to_path_buf(),
+    )?;
+    let batch = BlockBatch::decode(Arc::new(block), value_type)?;
+    let mut block_stats = BlockStats::default();
+    let mut invalid_utf8 = 0;
+    let mut bloom_missing = false;
+    let mut hashes = HashSet::new();
+    for value_idx in 0..batch.len() {
+        if batch.is_null(value_idx) {
+            block_stats.update_null();
+            continue;
+        }
+        let value = match batch.values() {
+            BatchValues::Str(_) | BatchValues::Dict(..) | BatchValues::Var(..) => {
+                match std::str::from_utf8(batch.str_bytes(value_idx)) {
+                    Ok(value) => DB721Type::Str(value.to_string()),
+                    Err(_) => {
+                        invalid_utf8 += 1;
+                        continue;
+                    }
+                }
+            }
+            _ => batch.get(value_idx),
+        };
+        block_stats.update(&value);
+        if rebuild_bloom {
+            hashes.extend(bloom::hash_value(&value));
+        }
+        if let Some(bloom) = block_meta.bloom() {
+            bloom_missing |=
+                bloom::hash_value(&value).is_some_and(|hash| !bloom.may_contain_hash(hash));
+        }
+    }
+    let mut actual = block_stats.finish(value_type);
+    actual.compressed_size = block_meta.compressed_size;
+    if rebuild_bloom {
+        // 与写入时一致，全是NULL的block不需要bloom filter
+        if !hashes.is_empty() {
+            let mut bloom = BloomFilter::with_capacity(hashes.len());
+            for hash in hashes {
+                bloom.insert_hash(hash);
+            }
+            actual.bloom = Some(bloom);
+        }
+    } else if !bloom_missing {
+        // 只检查时不重新生成，包含所有值时沿用footer中的bloom filter
+        actual.bloom = block_meta.bloom.clone();
+    }
+    Ok((actual, invalid_utf8))
+}
+/// 比较footer中的统计信息与实际数据
+fn check_block_meta(
+    column_name: &str,
+    block_idx: i32,
+    stored: &BlockMeta,
+    actual: &BlockMeta,
+    findings: &mut Vec<Finding>,
+) {
+    if stored.bloom.is_some() && actual.bloom.is_none() {
+        findings.push(Finding::block(
+            column_name,
+            block_idx,
+            String::from("bloom filter does not contain every value in data"),
+        ));
+    }
+    let mut mismatch = |name: &str, stored: String, actual: String| {
+        findings.push(Finding::block(
+            column_name,
+            block_idx,
+            format!("{} is {} in footer, but {} in data", name, stored, actual),
+        ));
+    };
+    if stored.null_count() != actual.null_count() {
+        mismatch(
+            "null_count",
+            stored.null_count().to_string(),
+            actual.null_count().to_string(),
+        );
+    }
+    if stored.has_nan() != actual.has_nan() {
+        mismatch(
+            "has_nan",
+            stored.has_nan().to_string(),
+            actual.has_nan().to_string(),
+        );
+    }
+    if stored.min != actual.min {
+        mismatch("min", stored.min.to_string(), actual.min.to_string());
+    }
+    if stored.max != actual.max {
+        mismatch("max", stored.max.to_string(), actual.max.to_string());
+    }
+    let len_to_string =
+        |len: Option<i32>| len.map_or(String::from("missing"), |len| len.to_string());
+    if stored.min_len != actual.min_len {
+        mismatch(
+            "min_len",
+            len_to_string(stored.min_len),
+            len_to_string(actual.min_len),
+        );
+    }
+    if stored.max_len != actual.max_len {
+        mismatch(
+            "max_len",
+            len_to_string(stored.max_len),
+            len_to_string(actual.max_len),
+        );
+    }
+}
+/// 检查一列，返回该列数据占用的字节范围
+fn check_column(
+    db721: &DB721,
+    column_name: &str,
+    column_meta: &ColumnMeta,
+    data_end: usize,
+    findings: &mut Vec<Finding>,
+) -> Option<(usize, usize)> {
+    if value_width(column_meta.value_type()).is_none() {
+        findings.push(Finding::column(
+            column_name,
+            format!("unsupported type {}", column_meta.value_type()),
+        ));
+        return None;
+    }
+    if column_meta.num_blocks < 0 || column_meta.start_offset < 0 {
+        findings.push(Finding::column(
+            column_name,
+            String::from("negative num_blocks or start_offset"),
+        ));
+        return None;
+    }
+    // block_stats的键必须恰好是 0..num_blocks
+    let mut keys = HashSet::new();
+    for key in column_meta.block_meta.keys() {
+        match key.parse::<i32>() {
+            Ok(block_idx) if (0..column_meta.num_blocks).contains(&block_idx) => {
+                keys.insert(block_idx);
+            }
+            _ => findings.push(Finding::column(
+                column_name,
+                format!("unexpected block_stats key {:?}", key),
+            )),
+        }
+    }
+    let start = column_meta.start_offset as usize;
+    let mut offset = start;
+    for block_idx in 0..column_meta.num_blocks {
+        if !keys.contains(&block_idx) {
+            // 缺少统计信息时无法确定之后各block的位置
+            findings.push(Finding::block(
+                column_name,
+                block_idx,
+                String::from("missing block_stats"),
+            ));
+            return Some((start, offset));
+        }
+        let block_meta = column_meta.block_meta(block_idx).unwrap();
+        if block_meta.value_num < 0 {
+            findings.push(Finding::block(
+                column_name,
+                block_idx,
+                format!("negative num {}", block_meta.value_num),
+            ));
+            return Some((start, offset));
+        }
+        if column_meta.codec() != Codec::None && block_meta.compressed_size.is_none() {
+            findings.push(Finding::block(
+                column_name,
+                block_idx,
+                String::from("missing compressed_size"),
+            ));
+            return Some((start, offset));
+        }
+        let end = offset + column_meta.stored_size(block_meta);
+        if end > data_end {
+            findings.push(Finding::block(
+                column_name,
+                block_idx,
+                format!(
+                    "bytes {}..{} run past the data region, which ends at {}",
+                    offset, end, data_end
+                ),
+            ));
+            return Some((start, offset));
+        }
+        match scan_block(
+            &db721.path,
+            column_meta.value_type(),
+            column_meta.codec(),
+            offset,
+            block_meta,
+            false,
+        ) {
+            Ok((actual, invalid_utf8)) => {
+                if invalid_utf8 > 0 {
+                    findings.push(Finding::block(
+                        column_name,
+                        block_idx,
+                        format!("{} strings are not valid UTF-8", invalid_utf8),
+                    ));
+                }
+                if block_meta.value_num > 0 {
+                    check_block_meta(column_name, block_idx, block_meta, &actual, findings);
+                }
+            }
+            Err(e) => findings.push(Finding::block(
+                column_name,
+                block_idx,
+                format!("failed to read: {}", e),
+            )),
+        }
+        offset = end;
+    }
+    Some((start, offset))
+}
+/// 检查整个文件，返回发现的问题，没有问题时为空
+pub fn check_file(path: PathBuf) -> Vec<Finding> {
+    let db721 = match DB721::open(path) {
+        Ok(db721) => db721,
+        Err(e) => return vec![Finding::file(format!("failed to read footer: {:#}", e))],
+    };
+    let mut findings = Vec::new();
+    let file_size = match std::fs::metadata(&db721.path) {
+        Ok(metadata) => metadata.len() as usize,
+        Err(e) => return vec![Finding::file(format!("failed to stat: {}", e))],
+    };
+    // 数据区在footer及其长度之前
+    let data_end = file_size.saturating_sub(db721.meta_size as usize + 4);
+    let columns = db721.meta.sorted_columns();
+    if columns.is_empty() {
+        findings.push(Finding::file(String::from("no columns")));
+    }
+    // 各列的总行数必须相同
+    let mut expected_rows: Option<(&str, usize)> = None;
+    for (column_name, column_meta) in columns.iter() {
+        let rows: usize = column_meta
+            .block_meta
+            .values()
+            .map(|block_meta| block_meta.value_num.max(0) as usize)
+            .sum();
+        match expected_rows {
+            None => expected_rows = Some((column_name.as_str(), rows)),
+            Some((first_column, first_rows)) if first_rows != rows => {
+                findings.push(Finding::column(
+                    column_name,
+                    format!(
+                        "has {} rows, but column {} has {}",
+                        rows, first_column, first_rows
+                    ),
+                ));
+            }
+            _ => {}
+        }
+    }
+    let mut ranges = Vec::new();
+    for (column_name, column_meta) in columns.iter() {
+        if let Some((start, end)) =
+            check_column(&db721, column_name, column_meta, data_end, &mut findings)
+        {
+            ranges.push((start, end, column_name.as_str()));
+        }
+    }
+    // 各列的数据不能相互重叠
+    ranges.sort();
+    for pair in ranges.windows(2) {
+        let (_, prev_end, prev_column) = pair[0];
+        let (start, _, column_name) = pair[1];
+        if start < prev_end {
+            findings.push(Finding::column(
+                column_name,
+                format!("data overlaps column {}", prev_column),
+            ));
+        }
+    }
+    findings
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::bloom::BloomFilter;
+    use crate::db721::check::check_file;
+    use crate::db721::writer::test_util::{rewrite_footer, write_test_file};
+    use crate::db721::DB721Type;
+
+    #[test]
+    fn test_check_bad_footer() {
+        let rows = (0..3).map(|id| [Some(DB721Type::Integer(id))]);
+        let file = write_test_file(
+            "check_bad_footer",
+            2,
+            &[("identifier", "int")],
+            rows,
+            |_| {},
+        );
+
+        // 长度超过文件本身的footer
+        let mut data = std::fs::read(&file.path).unwrap();
+        let trailer = data.len() - 4;
+        data[trailer..].copy_from_slice(&1_000_000i32.to_le_bytes());
+        std::fs::write(&file.path, &data).unwrap();
+        let findings = check_file(file.path.clone());
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].column_name, None);
+    }
+    #[test]
+    fn test_check_wrong_block_stats() {
+        let rows = (0..3).map(|id| {
+            [
+                Some(DB721Type::Integer(id)),
+                Some(DB721Type::Str(String::from("MALE"))),
+            ]
+        });
+        let file = write_test_file(
+            "check_wrong_block_stats",
+            2,
+            &[("identifier", "int"), ("sex", "str")],
+            rows,
+            |_| {},
+        );
+        let mut meta = file.meta.clone();
+        let identifier = meta.column_meta.get_mut("identifier").unwrap();
+        identifier.block_meta.get_mut("0").unwrap().max = DB721Type::Integer(100);
+        let sex = meta.column_meta.get_mut("sex").unwrap();
+        let block_meta = sex.block_meta.remove("1").unwrap();
+        sex.block_meta.insert(String::from("7"), block_meta);
+        rewrite_footer(&file.path, &meta);
+
+        let findings: Vec<String> = check_file(file.path.clone())
+            .iter()
+            .map(|finding| finding.to_string())
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                "column identifier: block 0: max is 100 in footer, but 1 in data",
+                "column sex: unexpected block_stats key \"7\"",
+                "column sex: block 1: missing block_stats",
+            ]
+        );
+    }
+    #[test]
+    fn test_check_bloom_filter() {
+        let rows = (0..6).map(|id| [Some(DB721Type::Integer(id))]);
+        let file = write_test_file(
+            "check_bloom_filter",
+            4,
+            &[("identifier", "int")],
+            rows,
+            |writer| {
+                writer
+                    .set_bloom_columns(&[String::from("identifier")])
+                    .unwrap();
+            },
+        );
+
+        // 全为0的bloom filter不包含任何值
+        let mut meta = file.meta.clone();
+        let identifier = meta.column_meta.get_mut("identifier").unwrap();
+        identifier.block_meta.get_mut("1").unwrap().bloom = Some(BloomFilter::with_capacity(2));
+        rewrite_footer(&file.path, &meta);
+
+        let findings: Vec<String> = check_file(file.path.clone())
+            .iter()
+            .map(|finding| finding.to_string())
+            .collect();
+        assert_eq!(
+            findings,
+            vec!["column identifier: block 1: bloom filter does not contain every value in data"]
+        );
+    }
+}
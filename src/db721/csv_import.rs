@@ -0,0 +1,283 @@
+//! 把CSV文件转换为db721文件，schema可以显式给出，也可以根据前若干行推断
+use crate::db721::writer::DB721Writer;
+use crate::db721::{binary, datetime, decimal, value_width, Codec, DB721Type};
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+
+pub struct CsvImportOptions {
+    /// 为空时取输出文件名（不含扩展名）
+    pub table_name: Option<String>,
+    pub max_values_per_block: i32,
+    /// (列名, 类型)，按位置对应CSV的列；为空时根据样本推断
+    pub columns: Option<Vec<(String, String)>>,
+    /// 推断schema时读取的行数
+    pub sample_rows: usize,
+    pub delimiter: u8,
+    /// 第一行是否为列名，没有列名时推断出的列名为 column_1, column_2, ...
+    pub has_header: bool,
+    pub codec: Codec,
+    /// 是否按block自动选择编码
+    pub auto_encoding: bool,
+    /// str列的所有block都使用变长布局，否则只有含超过32字节的值的block使用
+    pub var_str: bool,
+    /// 生成bloom filter的列
+    pub bloom_columns: Vec<String>,
+}
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        Self {
+            table_name: None,
+            max_values_per_block: 50000,
+            columns: None,
+            sample_rows: 1000,
+            delimiter: b',',
+            has_header: true,
+            codec: Codec::None,
+            auto_encoding: false,
+            var_str: false,
+            bloom_columns: Vec::new(),
+        }
+    }
+}
+/// 解析 "identifier:int,farm_name:str" 形式的schema，decimal(12,2)括号中的逗号不分隔列
+pub fn parse_schema(schema: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut columns = Vec::new();
+    let mut depth = 0;
+    let column_defs = schema.split(|c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        c == ',' && depth == 0
+    });
+    for column in column_defs {
+        let Some((column_name, value_type)) = column.split_once(':') else {
+            bail!("invalid column {}, expected name:type", column.trim());
+        };
+        let value_type = value_type.trim();
+        if value_width(value_type).is_none() {
+            bail!("no support for value type = {}", value_type);
+        }
+        columns.push((column_name.trim().to_string(), value_type.to_string()));
+    }
+    Ok(columns)
+}
+fn csv_reader(
+    csv_path: &Path,
+    options: &CsvImportOptions,
+) -> anyhow::Result<csv::Reader<std::fs::File>> {
+    csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_header)
+        .from_path(csv_path)
+        .with_context(|| format!("failed to open {}", csv_path.display()))
+}
+/// 按前sample_rows行推断每一列的类型：都能解析为i32时为int，其次i64为bigint，
+/// f32为float，否则为str。bool、date、timestamp列需要显式给出schema。
+/// 空字段会作为NULL写入，不参与推断
+pub fn infer_schema(
+    csv_path: &Path,
+    options: &CsvImportOptions,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut reader = csv_reader(csv_path, options)?;
+    let mut column_names: Vec<String> = if options.has_header {
+        reader
+            .headers()?
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut is_int: Vec<bool> = vec![true; column_names.len()];
+    let mut is_bigint: Vec<bool> = vec![true; column_names.len()];
+    let mut is_float: Vec<bool> = vec![true; column_names.len()];
+    for record in reader.records().take(options.sample_rows) {
+        let record = record?;
+        if column_names.is_empty() && !options.has_header {
+            column_names = (1..=record.len())
+                .map(|idx| format!("column_{}", idx))
+                .collect();
+            is_int = vec![true; record.len()];
+            is_bigint = vec![true; record.len()];
+            is_float = vec![true; record.len()];
+        }
+        for (idx, value) in record.iter().enumerate().take(column_names.len()) {
+            if value.is_empty() {
+                continue;
+            }
+            is_int[idx] &= value.parse::<i32>().is_ok();
+            is_bigint[idx] &= value.parse::<i64>().is_ok();
+            is_float[idx] &= value.parse::<f32>().is_ok();
+        }
+    }
+    if column_names.is_empty() {
+        bail!("{} has no columns", csv_path.display());
+    }
+    Ok(column_names
+        .into_iter()
+        .enumerate()
+        .map(|(idx, column_name)| {
+            let value_type = if is_int[idx] {
+                "int"
+            } else if is_bigint[idx] {
+                "bigint"
+            } else if is_float[idx] {
+                "float"
+            } else {
+                "str"
+            };
+            (column_name, value_type.to_string())
+        })
+        .collect())
+}
+/// str以外的列的空字段为NULL，str列的空字段仍为空字符串
+fn parse_value(value: &str, value_type: &str) -> anyhow::Result<Option<DB721Type>> {
+    Ok(match value_type {
+        "str" => Some(DB721Type::Str(value.to_string())),
+        _ if value.is_empty() => None,
+        "int" => Some(DB721Type::Integer(value.parse()?)),
+        "bigint" => Some(DB721Type::BigInt(value.parse()?)),
+        "float" => Some(DB721Type::Float(value.parse()?)),
+        "double" => Some(DB721Type::Double(value.parse()?)),
+        "bool" => Some(DB721Type::Bool(parse_bool(value)?)),
+        "date" => match datetime::parse_date(value) {
+            Some(days) => Some(DB721Type::Date(days)),
+            None => bail!("invalid date {}, expected YYYY-MM-DD", value),
+        },
+        "timestamp" => match datetime::parse_timestamp(value) {
+            Some(micros) => Some(DB721Type::Timestamp(micros)),
+            None => bail!("invalid timestamp {}", value),
+        },
+        _ if decimal::params(value_type).is_some() => Some(DB721Type::Decimal(value.parse()?)),
+        _ if binary::params(value_type).is_some() => Some(DB721Type::Binary(value.parse()?)),
+        _ => bail!("no support for value type = {}", value_type),
+    })
+}
+/// 接受true/false、t/f、1/0，不区分大小写
+fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "t" | "1" => Ok(true),
+        "false" | "f" | "0" => Ok(false),
+        _ => bail!("invalid bool {}", value),
+    }
+}
+/// 转换整个CSV文件，返回写入的行数
+pub fn import_csv(
+    csv_path: &Path,
+    out_path: PathBuf,
+    options: &CsvImportOptions,
+) -> anyhow::Result<usize> {
+    let columns = match &options.columns {
+        Some(columns) => columns.clone(),
+        None => infer_schema(csv_path, options)?,
+    };
+    let table_name = match &options.table_name {
+        Some(table_name) => table_name.clone(),
+        None => out_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("db721")
+            .to_string(),
+    };
+    let value_types: Vec<String> = columns
+        .iter()
+        .map(|(_, value_type)| value_type.clone())
+        .collect();
+    let mut writer = DB721Writer::create(
+        out_path,
+        table_name,
+        options.max_values_per_block,
+        columns.clone(),
+    )?;
+    writer.set_codec(options.codec);
+    writer.set_auto_encoding(options.auto_encoding);
+    writer.set_var_str(options.var_str);
+    writer.set_bloom_columns(&options.bloom_columns)?;
+    let mut reader = csv_reader(csv_path, options)?;
+    let mut row = Vec::with_capacity(columns.len());
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        if record.len() != columns.len() {
+            bail!(
+                "line {}: expected {} fields, got {}",
+                line,
+                columns.len(),
+                record.len()
+            );
+        }
+        row.clear();
+        for ((value, value_type), (column_name, _)) in
+            record.iter().zip(value_types.iter()).zip(columns.iter())
+        {
+            let value = parse_value(value, value_type).with_context(|| {
+                format!(
+                    "line {}: invalid {} value {:?} for column {}",
+                    line, value_type, value, column_name
+                )
+            })?;
+            row.push(value);
+        }
+        writer.write_nullable_row(&row)?;
+    }
+    let row_count = writer.row_count();
+    writer.finish()?;
+    Ok(row_count)
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::csv_import::{import_csv, infer_schema, parse_schema, CsvImportOptions};
+    use crate::db721::{DB721Type, DB721};
+
+    #[test]
+    fn test_csv_import() {
+        let csv_path = std::env::temp_dir().join("db721_csv_import.csv");
+        let out_path = std::env::temp_dir().join("db721_csv_import.db721");
+        std::fs::write(
+            &csv_path,
+            "identifier,farm_name,weight_g\n1,Cheep Birds,1351.5\n2,\"Hen, House\",532\n3,Cheep Birds,1898\n",
+        )
+        .unwrap();
+        let options = CsvImportOptions {
+            max_values_per_block: 2,
+            ..Default::default()
+        };
+        let columns = infer_schema(&csv_path, &options).unwrap();
+        assert_eq!(
+            columns,
+            parse_schema("identifier:int,farm_name:str,weight_g:float").unwrap()
+        );
+        assert_eq!(
+            import_csv(&csv_path, out_path.clone(), &options).unwrap(),
+            3
+        );
+        let db721 = DB721::open(out_path.clone()).unwrap();
+        assert_eq!(db721.meta.table_name(), "db721_csv_import");
+        assert_eq!(db721.row_count(), 3);
+        let farm_meta = db721.meta.column_meta.get("farm_name").unwrap();
+        assert_eq!(
+            farm_meta.block_meta(0).unwrap().max(),
+            &DB721Type::Str(String::from("Hen, House"))
+        );
+
+        std::fs::write(&csv_path, "identifier\n1\nx\n").unwrap();
+        let options = CsvImportOptions {
+            columns: Some(parse_schema("identifier:int").unwrap()),
+            ..Default::default()
+        };
+        assert!(import_csv(&csv_path, out_path.clone(), &options).is_err());
+
+        // decimal(p,s)中的逗号不分隔列
+        assert_eq!(
+            parse_schema("id:int, amount:decimal(12,2)").unwrap(),
+            vec![
+                (String::from("id"), String::from("int")),
+                (String::from("amount"), String::from("decimal(12,2)"))
+            ]
+        );
+        std::fs::remove_file(csv_path).unwrap();
+        std::fs::remove_file(out_path).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+//! 由多个schema相同的db721文件组成的一张表
+use crate::db721::predicate::Predicate;
+use crate::db721::DB721;
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// 外部表的数据来源，对应外部表的 filename / filenames / directory 选项
+#[derive(Debug, Clone)]
+pub enum DB721Source {
+    /// 单个文件
+    File(PathBuf),
+    /// glob模式，例如 /data/chickens-*.db721
+    Glob(String),
+    /// 目录（递归）下的所有 .db721 文件
+    Directory(PathBuf),
+}
+impl DB721Source {
+    /// 列出所有文件，按路径排序以保证扫描顺序稳定
+    pub fn list_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = match self {
+            DB721Source::File(path) => vec![path.clone()],
+            DB721Source::Glob(pattern) => {
+                let mut files = Vec::new();
+                for entry in glob::glob(pattern)
+                    .with_context(|| format!("invalid glob pattern {}", pattern))?
+                {
+                    let path = entry?;
+                    if path.is_file() {
+                        files.push(path);
+                    }
+                }
+                files
+            }
+            DB721Source::Directory(dir) => {
+                let mut files = Vec::new();
+                list_db721_files(dir, &mut files)?;
+                files
+            }
+        };
+        files.sort();
+        Ok(files)
+    }
+}
+fn list_db721_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_db721_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "db721") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+/// 解析路径中hive风格的分区目录，例如
+/// farm=Cheep Birds/date=2023-01-01/part.db721 得到 farm 和 date 两个分区列
+pub fn parse_partition_values(path: &Path) -> HashMap<String, String> {
+    let mut partition_values = HashMap::new();
+    let Some(parent) = path.parent() else {
+        return partition_values;
+    };
+    for component in parent.components() {
+        let Some(component) = component.as_os_str().to_str() else {
+            continue;
+        };
+        if let Some((key, value)) = component.split_once('=') {
+            if !key.is_empty() {
+                partition_values.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    partition_values
+}
+/// 检查两个文件的列名与列类型是否完全一致
+pub fn check_schema_compatible(base: &DB721, other: &DB721) -> anyhow::Result<()> {
+    let base_columns = &base.meta.column_meta;
+    let other_columns = &other.meta.column_meta;
+    if base_columns.len() != other_columns.len() {
+        bail!(
+            "{} has {} columns, but {} has {}",
+            other.path.display(),
+            other_columns.len(),
+            base.path.display(),
+            base_columns.len()
+        );
+    }
+    for (column_name, base_column) in base_columns.iter() {
+        let Some(other_column) = other_columns.get(column_name) else {
+            bail!("column {} is missing in {}", column_name, other.path.display());
+        };
+        if base_column.value_type() != other_column.value_type() {
+            bail!(
+                "column {} is {} in {}, but {} in {}",
+                column_name,
+                other_column.value_type(),
+                other.path.display(),
+                base_column.value_type(),
+                base.path.display()
+            );
+        }
+    }
+    Ok(())
+}
+/// 一张外部表对应的所有文件（已打开并读取元信息）
+#[derive(Debug, Clone)]
+pub struct DB721Dataset {
+    pub files: Vec<DB721>,
+}
+impl DB721Dataset {
+    /// 打开source中的所有文件并检查schema是否兼容
+    pub fn open(source: &DB721Source) -> anyhow::Result<Self> {
+        Self::open_with_predicates(source, &[])
+    }
+    /// 在打开文件之前，先用分区列上的谓词过滤掉不可能满足的文件
+    pub fn open_with_predicates(
+        source: &DB721Source,
+        predicates: &[Predicate],
+    ) -> anyhow::Result<Self> {
+        let paths = source.list_files()?;
+        if paths.is_empty() {
+            bail!("no db721 file found in {:?}", source);
+        }
+        let mut files: Vec<DB721> = Vec::new();
+        for path in paths {
+            let partition_values = parse_partition_values(&path);
+            let partition_may_match = predicates.iter().all(|predicate| {
+                match partition_values.get(&predicate.column_name) {
+                    Some(partition_value) => predicate.may_match_partition(partition_value),
+                    None => true,
+                }
+            });
+            if !partition_may_match {
+                continue;
+            }
+            let mut db721 = DB721::open(path.clone())
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            db721.partition_values = partition_values;
+            if let Some(first) = files.first() {
+                check_schema_compatible(first, &db721)?;
+            }
+            files.push(db721);
+        }
+        Ok(Self { files })
+    }
+    pub fn row_count(&self) -> usize {
+        self.files.iter().map(|db721| db721.row_count()).sum()
+    }
+    /// 去掉所有block都被谓词排除的文件
+    pub fn prune(&mut self, predicates: &[Predicate]) {
+        self.files.retain(|db721| db721.may_match(predicates));
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::dataset::parse_partition_values;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_partition_values() {
+        let path = PathBuf::from("/data/farm=Cheep Birds/date=2023-01-01/part.db721");
+        let partition_values = parse_partition_values(&path);
+        assert_eq!(partition_values.len(), 2);
+        assert_eq!(partition_values.get("farm").unwrap(), "Cheep Birds");
+        assert_eq!(partition_values.get("date").unwrap(), "2023-01-01");
+        assert!(parse_partition_values(&PathBuf::from("/data/part.db721")).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+//! date和timestamp列的取值及文本格式。date为自1970-01-01起的天数（i32），
+//! timestamp为自1970-01-01 00:00:00 UTC起的微秒数（i64）
+//!
+//! 日期与天数的转换参考 http://howardhinnant.github.io/date_algorithms.html
+
+/// postgres的date以2000-01-01为起点，与1970-01-01相差的天数
+pub const PG_EPOCH_DAYS: i32 = 10957;
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+/// postgres的timestamp以2000-01-01 00:00:00为起点，与1970-01-01相差的微秒数
+pub const PG_EPOCH_MICROS: i64 = PG_EPOCH_DAYS as i64 * MICROS_PER_DAY;
+
+/// 自1970-01-01起的天数对应的 (年, 月, 日)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+/// (年, 月, 日) 对应的自1970-01-01起的天数，不检查日期是否存在
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+/// 格式化为YYYY-MM-DD
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+/// 解析YYYY-MM-DD，日期不存在时返回None
+pub fn parse_date(value: &str) -> Option<i32> {
+    let mut parts = value.trim().splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    let days = days_from_civil(year, month, day);
+    // 2023-02-30这样的日期换算回来不一致
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    i32::try_from(days).ok()
+}
+/// 格式化为 `YYYY-MM-DD HH:MM:SS[.ffffff]+00`，与postgres在UTC时区下的输出一致
+pub fn format_timestamp(micros: i64) -> String {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let micros_of_day = micros.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let seconds = micros_of_day / MICROS_PER_SECOND;
+    let mut res = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    let fraction = micros_of_day % MICROS_PER_SECOND;
+    if fraction != 0 {
+        res.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+    }
+    res.push_str("+00");
+    res
+}
+/// 解析 `YYYY-MM-DD[( |T)HH:MM[:SS[.ffffff]]][Z|±HH[:MM]]`，没有时区时按UTC处理
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (date, rest) = match value.find([' ', 'T']) {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, ""),
+    };
+    let days = parse_date(date)? as i64;
+    let (time, offset_seconds) = match rest.find(['Z', '+', '-']) {
+        Some(pos) => (&rest[..pos], parse_offset(&rest[pos..])?),
+        None => (rest, 0),
+    };
+    let micros_of_day = if time.is_empty() {
+        0
+    } else {
+        parse_time(time.trim())?
+    };
+    days.checked_mul(MICROS_PER_DAY)?
+        .checked_add(micros_of_day)?
+        .checked_sub(offset_seconds * MICROS_PER_SECOND)
+}
+/// 解析HH:MM[:SS[.ffffff]]，返回当天的微秒数，超过6位的小数被截断
+fn parse_time(time: &str) -> Option<i64> {
+    let mut parts = time.splitn(3, ':');
+    let hour: i64 = parts.next()?.parse().ok()?;
+    let minute: i64 = parts.next()?.parse().ok()?;
+    let (second, fraction) = match parts.next() {
+        Some(second) => match second.split_once('.') {
+            Some((second, fraction)) => (second.parse().ok()?, fraction),
+            None => (second.parse().ok()?, ""),
+        },
+        None => (0i64, ""),
+    };
+    if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..60).contains(&second) {
+        return None;
+    }
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut micros = 0i64;
+    for idx in 0..6 {
+        let digit = fraction
+            .as_bytes()
+            .get(idx)
+            .map_or(0, |b| (b - b'0') as i64);
+        micros = micros * 10 + digit;
+    }
+    Some(((hour * 60 + minute) * 60 + second) * MICROS_PER_SECOND + micros)
+}
+/// 解析Z、±HH、±HHMM或±HH:MM，返回相对UTC的秒数
+fn parse_offset(offset: &str) -> Option<i64> {
+    if offset == "Z" {
+        return Some(0);
+    }
+    let sign = match offset.as_bytes()[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits: String = offset[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hour, minute) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i64>().ok()?,
+            digits[2..].parse::<i64>().ok()?,
+        ),
+        _ => return None,
+    };
+    if hour > 15 || minute >= 60 {
+        return None;
+    }
+    Some(sign * (hour * 3600 + minute * 60))
+}
+/// postgres的date转换为自1970-01-01起的天数，infinity返回None
+pub fn date_from_pg(pg_days: i32) -> Option<i32> {
+    if pg_days == i32::MIN || pg_days == i32::MAX {
+        return None;
+    }
+    pg_days.checked_add(PG_EPOCH_DAYS)
+}
+pub fn date_to_pg(days: i32) -> i32 {
+    days.saturating_sub(PG_EPOCH_DAYS)
+}
+/// postgres的timestamptz转换为自1970-01-01起的微秒数，infinity返回None
+pub fn timestamp_from_pg(pg_micros: i64) -> Option<i64> {
+    if pg_micros == i64::MIN || pg_micros == i64::MAX {
+        return None;
+    }
+    pg_micros.checked_add(PG_EPOCH_MICROS)
+}
+pub fn timestamp_to_pg(micros: i64) -> i64 {
+    micros.saturating_sub(PG_EPOCH_MICROS)
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::datetime::{format_date, format_timestamp, parse_date, parse_timestamp};
+
+    #[test]
+    fn test_date_round_trip() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-01-01"), Some(10957));
+        assert_eq!(parse_date("1969-12-31"), Some(-1));
+        assert_eq!(parse_date("2024-02-29"), Some(19782));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2023-13-01"), None);
+        assert_eq!(parse_date("2023-01"), None);
+        for days in [-700000, -1, 0, 19782, 2932896] {
+            assert_eq!(parse_date(&format_date(days)), Some(days));
+        }
+    }
+    #[test]
+    fn test_timestamp_round_trip() {
+        assert_eq!(parse_timestamp("1970-01-01 00:00:00+00"), Some(0));
+        assert_eq!(parse_timestamp("1970-01-01"), Some(0));
+        assert_eq!(parse_timestamp("1970-01-01T00:00:01.5Z"), Some(1_500_000));
+        assert_eq!(parse_timestamp("1970-01-01 08:00:00+08:00"), Some(0));
+        assert_eq!(
+            parse_timestamp("1969-12-31 23:59:59-00:00"),
+            Some(-1_000_000)
+        );
+        assert_eq!(parse_timestamp("2023-01-01 24:00:00"), None);
+        assert_eq!(
+            format_timestamp(1_672_574_400_000_000),
+            "2023-01-01 12:00:00+00"
+        );
+        assert_eq!(format_timestamp(-1), "1969-12-31 23:59:59.999999+00");
+        for micros in [-1, 0, 1_250_000, 1_672_574_400_123_456] {
+            assert_eq!(parse_timestamp(&format_timestamp(micros)), Some(micros));
+        }
+    }
+}
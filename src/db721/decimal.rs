@@ -0,0 +1,173 @@
+//! decimal列：按scale缩放后的整数，精度不超过18时存为i64，否则存为i128，均为小端。
+//! 列的类型写作 `decimal(precision,scale)`，footer中为 `"type": "decimal"` 加上precision和scale，
+//! block统计信息中的min/max写为十进制字符串，避免JSON数字的精度损失
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// 最大精度，i128可以表示所有38位十进制数
+pub const MAX_PRECISION: u32 = 38;
+/// 精度不超过该值时存为i64
+pub const MAX_PRECISION_64: u32 = 18;
+
+/// 值为 value / 10^scale
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    value: i128,
+    scale: u32,
+}
+impl Decimal {
+    pub fn new(value: i128, scale: u32) -> Self {
+        Self { value, scale }
+    }
+    pub fn value(&self) -> i128 {
+        self.value
+    }
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+    /// 转换为给定的scale，需要舍入或溢出时返回None
+    pub fn rescale(&self, scale: u32) -> Option<Self> {
+        let value = if scale >= self.scale {
+            self.value
+                .checked_mul(10i128.checked_pow(scale - self.scale)?)?
+        } else {
+            let factor = 10i128.checked_pow(self.scale - scale)?;
+            if self.value % factor != 0 {
+                return None;
+            }
+            self.value / factor
+        };
+        Some(Self { value, scale })
+    }
+    /// 整数部分和小数部分的总位数是否不超过precision
+    pub fn fits(&self, precision: u32) -> bool {
+        self.value.unsigned_abs() < 10u128.pow(precision)
+    }
+}
+impl FromStr for Decimal {
+    type Err = anyhow::Error;
+    /// 接受 `-123.45`、`+1`、`.5` 这样的十进制数，scale为小数位数
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        let (negative, digits) = match value.as_bytes().first() {
+            Some(b'-') => (true, &value[1..]),
+            Some(b'+') => (false, &value[1..]),
+            _ => (false, value),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            anyhow::bail!("invalid decimal {}", value);
+        }
+        if !integer.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+            anyhow::bail!("invalid decimal {}", value);
+        }
+        let mut res = 0i128;
+        for b in integer.bytes().chain(fraction.bytes()) {
+            res = res
+                .checked_mul(10)
+                .and_then(|res| res.checked_add((b - b'0') as i128))
+                .filter(|res| res.unsigned_abs() < 10u128.pow(MAX_PRECISION))
+                .ok_or_else(|| anyhow::anyhow!("decimal {} has more than 38 digits", value))?;
+        }
+        Ok(Self {
+            value: if negative { -res } else { res },
+            scale: fraction.len() as u32,
+        })
+    }
+}
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.value < 0 { "-" } else { "" };
+        let digits = self.value.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, integer, fraction)
+    }
+}
+/// scale不同的值先转换为较大的scale再比较，溢出时无法比较
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let scale = self.scale.max(other.scale);
+        let lhs = self.rescale(scale)?;
+        let rhs = other.rescale(scale)?;
+        Some(lhs.value.cmp(&rhs.value))
+    }
+}
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+/// 解析 `decimal(precision,scale)`，要求 1 <= precision <= 38 且 0 <= scale <= precision
+pub fn params(value_type: &str) -> Option<(u32, u32)> {
+    let params = value_type.strip_prefix("decimal(")?.strip_suffix(')')?;
+    let (precision, scale) = params.split_once(',')?;
+    let precision: u32 = precision.trim().parse().ok()?;
+    let scale: u32 = scale.trim().parse().ok()?;
+    if precision == 0 || precision > MAX_PRECISION || scale > precision {
+        return None;
+    }
+    Some((precision, scale))
+}
+pub fn type_name(precision: u32, scale: u32) -> String {
+    format!("decimal({},{})", precision, scale)
+}
+/// 每个值占用的字节数
+pub fn width(precision: u32) -> usize {
+    if precision <= MAX_PRECISION_64 {
+        8
+    } else {
+        16
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::decimal::{params, Decimal};
+
+    #[test]
+    fn test_decimal_parse_and_compare() {
+        let decimal = |value: &str| value.parse::<Decimal>().unwrap();
+        assert_eq!(decimal("123.45").value(), 12345);
+        assert_eq!(decimal("123.45").scale(), 2);
+        assert_eq!(decimal("-0.05").to_string(), "-0.05");
+        assert_eq!(decimal(".5").to_string(), "0.5");
+        assert_eq!(decimal("+7").to_string(), "7");
+        assert!("1.2.3".parse::<Decimal>().is_err());
+        assert!("-".parse::<Decimal>().is_err());
+        assert!("1e5".parse::<Decimal>().is_err());
+        assert!("123456789012345678901234567890123456789".parse::<Decimal>().is_err());
+        assert_eq!(
+            decimal("12345678901234567890123456789012345.678").to_string(),
+            "12345678901234567890123456789012345.678"
+        );
+        // 不同scale的值按数值比较
+        assert_eq!(decimal("1.50"), decimal("1.5"));
+        assert!(decimal("1.05") < decimal("1.5"));
+        assert!(decimal("-2") < decimal("-1.99"));
+        assert_eq!(decimal("1.50").rescale(1).unwrap().value(), 15);
+        assert!(decimal("1.55").rescale(1).is_none());
+        assert!(decimal("99.99").fits(4));
+        assert!(!decimal("100.00").fits(4));
+        assert_eq!(params("decimal(12,2)"), Some((12, 2)));
+        assert_eq!(params("decimal(38, 38)"), Some((38, 38)));
+        assert_eq!(params("decimal(39,2)"), None);
+        assert_eq!(params("decimal(2,3)"), None);
+        assert_eq!(params("decimal"), None);
+    }
+}
@@ -0,0 +1,285 @@
+//! int列的rle、delta编码以及str列的变长布局。这些编码都可以从block中任意一行开始解码，
+//! 不需要先解码该行之前的数据
+//!
+//! rle：若干个8字节的run，每个run为 (值 i32, 到该run为止的累计行数 u32)，均为小端。
+//! 累计行数递增，按它二分查找起始行所在的run
+//!
+//! delta：每DELTA_GROUP_SIZE行为一组，开头是每组相对于组数据起点的字节偏移（u32小端），
+//! 之后是各组的数据：组内第一个值（i32小端），其余每个值为delta-of-delta的zigzag varint，
+//! 组内第一个delta之前的delta视为0
+//!
+//! var：num+1个相对于字符串数据起点的字节偏移（u32小端），之后是所有字符串拼接在一起的数据，
+//! 第idx个字符串为偏移idx到idx+1之间的字节
+use crate::db721::{InterruptCheck, INTERRUPT_CHECK_INTERVAL};
+use anyhow::bail;
+
+/// rle中每个run占用的字节数
+pub const RLE_RUN_WIDTH: usize = 8;
+/// delta编码每组的行数，跳到某一行最多只需解码一组中该行之前的值
+pub const DELTA_GROUP_SIZE: usize = 128;
+
+pub fn rle_encode(values: &[i32]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut idx = 0;
+    while idx < values.len() {
+        let value = values[idx];
+        while idx < values.len() && values[idx] == value {
+            idx += 1;
+        }
+        data.extend_from_slice(&value.to_le_bytes());
+        data.extend_from_slice(&(idx as u32).to_le_bytes());
+    }
+    data
+}
+/// 解码第start行到最后一行（共value_num行）
+pub fn rle_decode_from(
+    data: &[u8],
+    value_num: usize,
+    start: usize,
+    interrupt_check: Option<InterruptCheck>,
+) -> anyhow::Result<Vec<i32>> {
+    if !data.len().is_multiple_of(RLE_RUN_WIDTH) {
+        bail!("rle data has {} bytes, not a multiple of runs", data.len());
+    }
+    let run_end = |run: usize| {
+        let offset = run * RLE_RUN_WIDTH + 4;
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+    };
+    let run_num = data.len() / RLE_RUN_WIDTH;
+    // 第一个累计行数大于start的run包含第start行
+    let (mut low, mut high) = (0, run_num);
+    while low < high {
+        let mid = (low + high) / 2;
+        if run_end(mid) <= start {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let mut values = Vec::with_capacity(value_num.saturating_sub(start));
+    let mut row = start;
+    for run in low..run_num {
+        if run % INTERRUPT_CHECK_INTERVAL == 0 {
+            if let Some(check) = interrupt_check {
+                check();
+            }
+        }
+        let offset = run * RLE_RUN_WIDTH;
+        let value = i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let end = run_end(run);
+        if end <= row || end > value_num {
+            bail!("rle run {} ends at row {}, out of order", run, end);
+        }
+        values.resize(values.len() + end - row, value);
+        row = end;
+    }
+    if row != value_num && value_num > start {
+        bail!("rle runs end at row {}, expected {}", row, value_num);
+    }
+    Ok(values)
+}
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+fn unzigzag(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+fn put_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+fn get_varint(data: &[u8], pos: &mut usize) -> anyhow::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let Some(byte) = data.get(*pos) else {
+            bail!("delta data ends inside a varint");
+        };
+        *pos += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("varint is longer than 5 bytes")
+}
+pub fn delta_encode(values: &[i32]) -> Vec<u8> {
+    let groups: Vec<&[i32]> = values.chunks(DELTA_GROUP_SIZE).collect();
+    let mut group_data = Vec::new();
+    let mut offsets = Vec::with_capacity(groups.len() * 4);
+    for group in groups {
+        offsets.extend_from_slice(&(group_data.len() as u32).to_le_bytes());
+        group_data.extend_from_slice(&group[0].to_le_bytes());
+        let mut delta = 0i32;
+        for pair in group.windows(2) {
+            // 使用回绕运算，解码时同样回绕即可还原
+            let next_delta = pair[1].wrapping_sub(pair[0]);
+            put_varint(&mut group_data, zigzag(next_delta.wrapping_sub(delta)));
+            delta = next_delta;
+        }
+    }
+    offsets.extend_from_slice(&group_data);
+    offsets
+}
+/// 解码第start行到最后一行（共value_num行），从start所在的组开始解码
+pub fn delta_decode_from(
+    data: &[u8],
+    value_num: usize,
+    start: usize,
+    interrupt_check: Option<InterruptCheck>,
+) -> anyhow::Result<Vec<i32>> {
+    let group_num = value_num.div_ceil(DELTA_GROUP_SIZE);
+    if data.len() < group_num * 4 {
+        bail!("delta data is shorter than its group offsets");
+    }
+    let (offsets, group_data) = data.split_at(group_num * 4);
+    let group_offset =
+        |group: usize| u32::from_le_bytes(offsets[group * 4..group * 4 + 4].try_into().unwrap());
+    let mut values = Vec::with_capacity(value_num.saturating_sub(start));
+    let first_group = start / DELTA_GROUP_SIZE;
+    let mut pos = if first_group < group_num {
+        group_offset(first_group) as usize
+    } else {
+        0
+    };
+    for group in first_group..group_num {
+        if (group * DELTA_GROUP_SIZE).is_multiple_of(INTERRUPT_CHECK_INTERVAL) {
+            if let Some(check) = interrupt_check {
+                check();
+            }
+        }
+        if pos != group_offset(group) as usize {
+            bail!("delta group {} does not start at its offset", group);
+        }
+        let Some(first) = group_data.get(pos..pos + 4) else {
+            bail!("delta data ends inside group {}", group);
+        };
+        pos += 4;
+        let mut value = i32::from_le_bytes(first.try_into().unwrap());
+        let mut delta = 0i32;
+        let group_start = group * DELTA_GROUP_SIZE;
+        let group_end = value_num.min(group_start + DELTA_GROUP_SIZE);
+        for row in group_start..group_end {
+            if row > group_start {
+                delta = delta.wrapping_add(unzigzag(get_varint(group_data, &mut pos)?));
+                value = value.wrapping_add(delta);
+            }
+            if row >= start {
+                values.push(value);
+            }
+        }
+    }
+    if pos != group_data.len() && first_group < group_num {
+        bail!("delta data has {} trailing bytes", group_data.len() - pos);
+    }
+    Ok(values)
+}
+pub fn var_encode<S: AsRef<str>>(values: &[S]) -> Vec<u8> {
+    let mut data = Vec::with_capacity((values.len() + 1) * 4);
+    let mut offset = 0u32;
+    data.extend_from_slice(&offset.to_le_bytes());
+    for value in values {
+        offset += value.as_ref().len() as u32;
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    for value in values {
+        data.extend_from_slice(value.as_ref().as_bytes());
+    }
+    data
+}
+/// 第start行到最后一行（共value_num行）的字符串在data中的起止位置，
+/// 返回的第idx个字符串为 data[ranges[idx]..ranges[idx + 1]]
+pub fn var_ranges_from(
+    data: &[u8],
+    value_num: usize,
+    start: usize,
+    interrupt_check: Option<InterruptCheck>,
+) -> anyhow::Result<Vec<usize>> {
+    let header = (value_num + 1) * 4;
+    if data.len() < header {
+        bail!("var data is shorter than its offsets");
+    }
+    let mut ranges = Vec::with_capacity(value_num + 1 - start);
+    for row in start..=value_num {
+        if row % INTERRUPT_CHECK_INTERVAL == 0 {
+            if let Some(check) = interrupt_check {
+                check();
+            }
+        }
+        let offset = u32::from_le_bytes(data[row * 4..row * 4 + 4].try_into().unwrap());
+        let position = header + offset as usize;
+        if position > data.len() || ranges.last().is_some_and(|last| position < *last) {
+            bail!("var offset {} of row {} is out of order", offset, row);
+        }
+        ranges.push(position);
+    }
+    Ok(ranges)
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::encoding::{
+        delta_decode_from, delta_encode, rle_decode_from, rle_encode, var_encode, var_ranges_from,
+        DELTA_GROUP_SIZE,
+    };
+
+    #[test]
+    fn test_rle_seek() {
+        let values: Vec<i32> = (0..1000).map(|idx| idx / 100).collect();
+        let data = rle_encode(&values);
+        assert_eq!(data.len(), 10 * 8);
+        for start in [0, 1, 99, 100, 555, 999, 1000] {
+            assert_eq!(
+                rle_decode_from(&data, values.len(), start, None).unwrap(),
+                values[start..]
+            );
+        }
+        assert!(rle_decode_from(&data, values.len() + 1, 0, None).is_err());
+    }
+    #[test]
+    fn test_delta_seek() {
+        let mut values: Vec<i32> = (0..1000).map(|idx| idx * 3 + idx % 7).collect();
+        values.extend([i32::MAX, i32::MIN, 0, -5]);
+        let data = delta_encode(&values);
+        assert!(data.len() < values.len() * 4);
+        for start in [
+            0,
+            1,
+            DELTA_GROUP_SIZE - 1,
+            DELTA_GROUP_SIZE,
+            777,
+            1003,
+            1004,
+        ] {
+            assert_eq!(
+                delta_decode_from(&data, values.len(), start, None).unwrap(),
+                values[start..]
+            );
+        }
+        assert!(delta_decode_from(&data[..data.len() - 1], values.len(), 0, None).is_err());
+    }
+    #[test]
+    fn test_var_seek() {
+        let values = [
+            "",
+            "MALE",
+            "a note that is much longer than thirty-two bytes",
+            "鸡",
+        ];
+        let data = var_encode(&values);
+        for start in 0..=values.len() {
+            let ranges = var_ranges_from(&data, values.len(), start, None).unwrap();
+            let decoded: Vec<&[u8]> = ranges
+                .windows(2)
+                .map(|pair| &data[pair[0]..pair[1]])
+                .collect();
+            let expected: Vec<&[u8]> = values[start..]
+                .iter()
+                .map(|value| value.as_bytes())
+                .collect();
+            assert_eq!(decoded, expected);
+        }
+        assert!(var_ranges_from(&data[..data.len() - 1], values.len(), 0, None).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+//! 可以下推到db721文件的简单谓词，利用block统计信息跳过整个block或文件
+use crate::db721::bloom;
+use crate::db721::decimal::Decimal;
+use crate::db721::{datetime, BlockMeta, DB721Type, DB721};
+use std::cmp::Ordering;
+use std::mem::discriminant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `column IN (...)`，即 `column = ANY(array)`
+    In,
+    /// `column IS NULL`，不使用value
+    IsNull,
+    /// `column IS NOT NULL`，不使用value
+    IsNotNull,
+}
+impl CompareOp {
+    /// btree操作符族中的策略号，1到5依次为 `<`、`<=`、`=`、`>=`、`>`
+    pub fn from_btree_strategy(strategy: u16) -> Option<Self> {
+        match strategy {
+            1 => Some(CompareOp::Lt),
+            2 => Some(CompareOp::Le),
+            3 => Some(CompareOp::Eq),
+            4 => Some(CompareOp::Ge),
+            5 => Some(CompareOp::Gt),
+            _ => None,
+        }
+    }
+    /// 交换左右操作数后对应的操作符，例如 `1 < col` 等价于 `col > 1`
+    pub fn commute(self) -> Self {
+        match self {
+            CompareOp::Eq => CompareOp::Eq,
+            CompareOp::Lt => CompareOp::Gt,
+            CompareOp::Le => CompareOp::Ge,
+            CompareOp::Gt => CompareOp::Lt,
+            CompareOp::Ge => CompareOp::Le,
+            op => op,
+        }
+    }
+}
+/// `column op value`，op为In时为 `column IN (value, in_values...)`
+#[derive(Debug, Clone)]
+pub struct Predicate {
+    pub column_name: String,
+    pub op: CompareOp,
+    pub value: DB721Type,
+    /// IN列表中除value以外的其余值
+    pub in_values: Vec<DB721Type>,
+}
+impl Predicate {
+    pub fn new(column_name: String, op: CompareOp, value: DB721Type) -> Self {
+        Self {
+            column_name,
+            op,
+            value,
+            in_values: Vec::new(),
+        }
+    }
+    /// `column IS NULL` / `column IS NOT NULL`
+    pub fn new_null_test(column_name: String, is_null: bool) -> Self {
+        let op = if is_null {
+            CompareOp::IsNull
+        } else {
+            CompareOp::IsNotNull
+        };
+        Self::new(column_name, op, DB721Type::Integer(0))
+    }
+    /// IN列表为空时返回None
+    pub fn new_in(column_name: String, mut values: Vec<DB721Type>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let value = values.remove(0);
+        Some(Self {
+            column_name,
+            op: CompareOp::In,
+            value,
+            in_values: values,
+        })
+    }
+    /// 需要等值比较的所有值，op为Eq或In时才有
+    fn eq_values(&self) -> Vec<&DB721Type> {
+        match self.op {
+            CompareOp::Eq => vec![&self.value],
+            CompareOp::In => std::iter::once(&self.value)
+                .chain(self.in_values.iter())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    /// 判断值域为[min, max]的数据中是否可能存在满足谓词的值，
+    /// 类型不一致无法比较或者是NULL判断时保守地返回true
+    pub fn may_match_range(&self, min: &DB721Type, max: &DB721Type) -> bool {
+        if self.op == CompareOp::IsNull || self.op == CompareOp::IsNotNull {
+            return true;
+        }
+        if self.op == CompareOp::In {
+            return self.eq_values().into_iter().any(|value| {
+                Predicate::new(self.column_name.clone(), CompareOp::Eq, value.clone())
+                    .may_match_range(min, max)
+            });
+        }
+        let value = widen(&self.value, min);
+        if discriminant(&value) != discriminant(min) || discriminant(&value) != discriminant(max) {
+            return true;
+        }
+        let (Some(cmp_min), Some(cmp_max)) = (value.partial_cmp(min), value.partial_cmp(max))
+        else {
+            return true;
+        };
+        match self.op {
+            CompareOp::Eq | CompareOp::In => {
+                cmp_min != Ordering::Less && cmp_max != Ordering::Greater
+            }
+            CompareOp::Lt => cmp_min == Ordering::Greater,
+            CompareOp::Le => cmp_min != Ordering::Less,
+            CompareOp::Gt => cmp_max == Ordering::Less,
+            CompareOp::Ge => cmp_max != Ordering::Greater,
+            CompareOp::IsNull | CompareOp::IsNotNull => true,
+        }
+    }
+    pub fn may_match_block(&self, block_meta: &BlockMeta) -> bool {
+        match self.op {
+            CompareOp::IsNull => return block_meta.null_count() > 0,
+            CompareOp::IsNotNull => return !block_meta.all_null(),
+            // 比较的结果对NULL不为真，全是NULL的block可以直接跳过
+            _ if block_meta.all_null() => return false,
+            _ => {}
+        }
+        // postgres中NaN大于所有数，NaN不计入max，有NaN的block不能按max排除 > 和 >=
+        if block_meta.has_nan() && matches!(self.op, CompareOp::Gt | CompareOp::Ge) {
+            return true;
+        }
+        if !self.may_match_range(&block_meta.min, &block_meta.max) {
+            return false;
+        }
+        // dict编码的block直接在字典中查找等值条件
+        if let Some(dictionary) = block_meta.dictionary() {
+            let eq_values = self.eq_values();
+            if !eq_values.is_empty()
+                && eq_values.iter().all(|value| matches!(value, DB721Type::Str(_)))
+            {
+                return eq_values.into_iter().any(|value| {
+                    let DB721Type::Str(value) = value else {
+                        return true;
+                    };
+                    dictionary.iter().any(|entry| entry == value)
+                });
+            }
+        }
+        // 有bloom filter的block按值的哈希判断是否可能包含等值条件中的值
+        if let Some(bloom) = block_meta.bloom() {
+            let eq_values = self.eq_values();
+            if !eq_values.is_empty() {
+                return eq_values.into_iter().any(|value| {
+                    match bloom_key(value, &block_meta.min).as_ref().and_then(bloom::hash_value) {
+                        Some(hash) => bloom.may_contain_hash(hash),
+                        None => true,
+                    }
+                });
+            }
+        }
+        true
+    }
+    /// 分区列的取值来自目录名，按谓词中常量（In时为第一个值）的类型解析后再比较，
+    /// 解析失败时保守地返回true
+    pub fn may_match_partition(&self, partition_value: &str) -> bool {
+        // 分区列的取值不会是NULL
+        match self.op {
+            CompareOp::IsNull => return false,
+            CompareOp::IsNotNull => return true,
+            _ => {}
+        }
+        let value = match &self.value {
+            DB721Type::Integer(_) => partition_value.parse().ok().map(DB721Type::Integer),
+            DB721Type::BigInt(_) => partition_value.parse().ok().map(DB721Type::BigInt),
+            DB721Type::Double(_) => partition_value.parse().ok().map(DB721Type::Double),
+            DB721Type::Float(_) => partition_value.parse().ok().map(DB721Type::Float),
+            DB721Type::Bool(_) => partition_value.parse().ok().map(DB721Type::Bool),
+            DB721Type::Str(_) => Some(DB721Type::Str(partition_value.to_string())),
+            DB721Type::Date(_) => datetime::parse_date(partition_value).map(DB721Type::Date),
+            DB721Type::Timestamp(_) => {
+                datetime::parse_timestamp(partition_value).map(DB721Type::Timestamp)
+            }
+            DB721Type::Decimal(_) => partition_value.parse().ok().map(DB721Type::Decimal),
+            DB721Type::Binary(_) => partition_value.parse().ok().map(DB721Type::Binary),
+        };
+        match value {
+            Some(value) => self.may_match_range(&value, &value),
+            None => true,
+        }
+    }
+}
+/// 把谓词中的常量无损地转换为统计信息的类型，例如bigint列与int常量比较，
+/// 无法无损转换时保持原样
+fn widen(value: &DB721Type, like: &DB721Type) -> DB721Type {
+    match (value, like) {
+        (DB721Type::Integer(val), DB721Type::BigInt(_)) => DB721Type::BigInt(*val as i64),
+        (DB721Type::Integer(val), DB721Type::Double(_)) => DB721Type::Double(*val as f64),
+        (DB721Type::Float(val), DB721Type::Double(_)) => DB721Type::Double(*val as f64),
+        (DB721Type::BigInt(val), DB721Type::Integer(_)) => match i32::try_from(*val) {
+            Ok(val) => DB721Type::Integer(val),
+            Err(_) => value.clone(),
+        },
+        (DB721Type::Integer(val), DB721Type::Decimal(_)) => {
+            DB721Type::Decimal(Decimal::new(*val as i128, 0))
+        }
+        (DB721Type::BigInt(val), DB721Type::Decimal(_)) => {
+            DB721Type::Decimal(Decimal::new(*val as i128, 0))
+        }
+        _ => value.clone(),
+    }
+}
+/// 把常量转换为与block中的值相同的类型和编码，decimal还需要转换到列的scale，无法转换时返回None
+fn bloom_key(value: &DB721Type, like: &DB721Type) -> Option<DB721Type> {
+    let value = widen(value, like);
+    if discriminant(&value) != discriminant(like) {
+        return None;
+    }
+    match (&value, like) {
+        (DB721Type::Decimal(val), DB721Type::Decimal(like)) => {
+            val.rescale(like.scale()).map(DB721Type::Decimal)
+        }
+        _ => Some(value),
+    }
+}
+impl DB721 {
+    /// 返回所有谓词都可能满足的block下标（升序）。由于每一列按相同的
+    /// max values per block切分，同一下标的block在各列中对应相同的行
+    pub fn matching_blocks(&self, predicates: &[Predicate]) -> Vec<i32> {
+        let num_blocks = self
+            .meta
+            .column_meta
+            .values()
+            .map(|column_meta| column_meta.num_blocks)
+            .max()
+            .unwrap_or(0);
+        (0..num_blocks)
+            .filter(|block_idx| {
+                predicates.iter().all(|predicate| {
+                    let Some(column_meta) = self.meta.column_meta.get(&predicate.column_name)
+                    else {
+                        return true;
+                    };
+                    match column_meta.block_meta.get(&block_idx.to_string()) {
+                        Some(block_meta) => predicate.may_match_block(block_meta),
+                        None => true,
+                    }
+                })
+            })
+            .collect()
+    }
+    /// 所有block的统计信息都排除了谓词时，整个文件都可以跳过
+    pub fn may_match(&self, predicates: &[Predicate]) -> bool {
+        !self.matching_blocks(predicates).is_empty()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::predicate::{CompareOp, Predicate};
+    use crate::db721::writer::BlockStats;
+    use crate::db721::DB721Type;
+
+    #[test]
+    fn test_predicate_may_match_range() {
+        let min = DB721Type::Integer(10);
+        let max = DB721Type::Integer(20);
+        let pred = |op, val| Predicate::new(String::from("identifier"), op, DB721Type::Integer(val));
+        assert!(pred(CompareOp::Eq, 10).may_match_range(&min, &max));
+        assert!(!pred(CompareOp::Eq, 21).may_match_range(&min, &max));
+        assert!(!pred(CompareOp::Lt, 10).may_match_range(&min, &max));
+        assert!(pred(CompareOp::Le, 10).may_match_range(&min, &max));
+        assert!(!pred(CompareOp::Gt, 20).may_match_range(&min, &max));
+        assert!(pred(CompareOp::Ge, 20).may_match_range(&min, &max));
+        // 类型不一致时不能跳过
+        let str_pred = Predicate::new(
+            String::from("identifier"),
+            CompareOp::Eq,
+            DB721Type::Str(String::from("10")),
+        );
+        assert!(str_pred.may_match_range(&min, &max));
+    }
+    #[test]
+    fn test_predicate_may_match_partition() {
+        let date_pred = Predicate::new(
+            String::from("date"),
+            CompareOp::Ge,
+            DB721Type::Str(String::from("2023-01-02")),
+        );
+        assert!(!date_pred.may_match_partition("2023-01-01"));
+        assert!(date_pred.may_match_partition("2023-01-02"));
+        let int_pred = Predicate::new(String::from("year"), CompareOp::Eq, DB721Type::Integer(2023));
+        assert!(int_pred.may_match_partition("2023"));
+        assert!(!int_pred.may_match_partition("2022"));
+        assert!(int_pred.may_match_partition("unknown"));
+    }
+    #[test]
+    fn test_predicate_in_and_dictionary() {
+        let sex = |value: &str| DB721Type::Str(String::from(value));
+        let in_pred = Predicate::new_in(String::from("sex"), vec![sex("AAA"), sex("MALE")]).unwrap();
+        assert!(in_pred.may_match_range(&sex("FEMALE"), &sex("MALE")));
+        assert!(!in_pred.may_match_range(&sex("B"), &sex("C")));
+        let block_meta = |dictionary: Vec<&str>| {
+            let mut block_meta = BlockStats::default().finish("str");
+            block_meta.min = sex("FEMALE");
+            block_meta.max = sex("UNKNOWN");
+            block_meta.dictionary = Some(dictionary.into_iter().map(String::from).collect());
+            block_meta
+        };
+        // MALE在min与max之间，但不在字典中
+        assert!(!in_pred.may_match_block(&block_meta(vec!["FEMALE", "UNKNOWN"])));
+        assert!(in_pred.may_match_block(&block_meta(vec!["FEMALE", "MALE", "UNKNOWN"])));
+        let eq_pred = Predicate::new(String::from("sex"), CompareOp::Eq, sex("MALE"));
+        assert!(!eq_pred.may_match_block(&block_meta(vec!["FEMALE", "UNKNOWN"])));
+        let lt_pred = Predicate::new(String::from("sex"), CompareOp::Lt, sex("MALE"));
+        assert!(lt_pred.may_match_block(&block_meta(vec!["FEMALE", "UNKNOWN"])));
+    }
+    #[test]
+    fn test_predicate_null_test() {
+        let block_meta = |value_num: i32, null_count: i32| {
+            let mut block_stats = BlockStats::default();
+            for idx in 0..value_num {
+                if idx < null_count {
+                    block_stats.update_null();
+                } else {
+                    block_stats.update(&DB721Type::Integer(idx));
+                }
+            }
+            block_stats.finish("int")
+        };
+        let is_null = Predicate::new_null_test(String::from("age"), true);
+        let is_not_null = Predicate::new_null_test(String::from("age"), false);
+        let eq_pred = Predicate::new(String::from("age"), CompareOp::Eq, DB721Type::Integer(0));
+        assert!(!is_null.may_match_block(&block_meta(10, 0)));
+        assert!(is_null.may_match_block(&block_meta(10, 3)));
+        assert!(is_not_null.may_match_block(&block_meta(10, 3)));
+        assert!(!is_not_null.may_match_block(&block_meta(10, 10)));
+        // 全是NULL的block的min/max为占位的0，但不能与0相等
+        assert!(!eq_pred.may_match_block(&block_meta(10, 10)));
+        assert!(!is_null.may_match_partition("2023"));
+        assert!(is_not_null.may_match_partition("2023"));
+    }
+    #[test]
+    fn test_predicate_wider_types() {
+        let min = DB721Type::BigInt(5_000_000_000);
+        let max = DB721Type::BigInt(6_000_000_000);
+        // bigint列与int常量比较时按bigint比较
+        let int_pred = Predicate::new(String::from("id"), CompareOp::Lt, DB721Type::Integer(10));
+        assert!(!int_pred.may_match_range(&min, &max));
+        let bigint_pred = Predicate::new(
+            String::from("id"),
+            CompareOp::Eq,
+            DB721Type::BigInt(5_500_000_000),
+        );
+        assert!(bigint_pred.may_match_range(&min, &max));
+        // 超出int范围的bigint常量无法转换，不能跳过
+        assert!(bigint_pred.may_match_range(&DB721Type::Integer(0), &DB721Type::Integer(10)));
+        let small_pred = Predicate::new(String::from("id"), CompareOp::Eq, DB721Type::BigInt(50));
+        assert!(!small_pred.may_match_range(&DB721Type::Integer(0), &DB721Type::Integer(10)));
+        let float_pred = Predicate::new(String::from("w"), CompareOp::Gt, DB721Type::Float(1.5));
+        assert!(!float_pred.may_match_range(&DB721Type::Double(0.0), &DB721Type::Double(1.5)));
+        let date_pred = Predicate::new(String::from("day"), CompareOp::Ge, DB721Type::Date(19358));
+        assert!(!date_pred.may_match_range(&DB721Type::Date(0), &DB721Type::Date(19357)));
+        assert!(date_pred.may_match_partition("2023-01-01"));
+        assert!(!date_pred.may_match_partition("2022-12-31"));
+        let ts_pred = Predicate::new(
+            String::from("ts"),
+            CompareOp::Lt,
+            DB721Type::Timestamp(1_672_574_400_000_000),
+        );
+        assert!(ts_pred.may_match_partition("2023-01-01"));
+        assert!(!ts_pred.may_match_partition("2023-01-01T12:00:00Z"));
+        let bool_pred = Predicate::new(String::from("ok"), CompareOp::Eq, DB721Type::Bool(true));
+        assert!(!bool_pred.may_match_range(&DB721Type::Bool(false), &DB721Type::Bool(false)));
+    }
+}
@@ -0,0 +1,337 @@
+//! 根据实际数据重新计算footer中的block统计信息，数据原样复制，只重新生成footer及其长度。
+//! 只需要footer中的表名、每个block的最大值个数以及各列的类型和起始偏移，
+//! 各列的数据到下一列的起始偏移（最后一列到footer）为止，并按最大值个数切分为block
+use crate::db721::bloom;
+use crate::db721::check::scan_block;
+use crate::db721::writer::{write_footer, BlockStats};
+use crate::db721::{binary, decimal, value_width, BlockMeta, Codec, ColumnMeta, DB721Meta};
+use anyhow::{bail, Context};
+use bytes::Buf;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct RawColumnMeta {
+    #[serde(rename = "type")]
+    value_type: String,
+    start_offset: i32,
+    #[serde(default)]
+    codec: Option<String>,
+    #[serde(default)]
+    precision: Option<u32>,
+    #[serde(default)]
+    scale: Option<u32>,
+    #[serde(default)]
+    width: Option<usize>,
+    /// 只用于判断block是否使用了编码、是否有bloom filter
+    #[serde(default)]
+    block_stats: HashMap<String, serde_json::Value>,
+}
+impl RawColumnMeta {
+    /// 与ColumnMeta::value_type一致，decimal列带上精度和小数位数，binary列带上宽度
+    fn value_type(&self) -> String {
+        match (self.value_type.as_str(), self.precision, self.width) {
+            ("decimal", Some(precision), _) => {
+                decimal::type_name(precision, self.scale.unwrap_or(0))
+            }
+            ("binary", _, Some(width)) => binary::type_name(width),
+            _ => self.value_type.clone(),
+        }
+    }
+}
+/// 宽松解析的footer，忽略block统计信息
+#[derive(Deserialize)]
+struct RawMeta {
+    #[serde(rename = "Table")]
+    table_name: String,
+    #[serde(rename = "Max Values Per Block")]
+    max_value_per_block: i32,
+    #[serde(rename = "Columns")]
+    column_meta: HashMap<String, RawColumnMeta>,
+}
+/// 读取footer，返回宽松解析的元信息以及数据区的结束位置
+fn read_raw_meta(file: &mut File) -> anyhow::Result<(RawMeta, u64)> {
+    let file_size = file.metadata()?.len();
+    if file_size < 4 {
+        bail!("file is too short");
+    }
+    file.seek(SeekFrom::Start(file_size - 4))?;
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf)?;
+    let meta_size = (&buf[..]).get_i32_le();
+    if meta_size <= 0 || meta_size as u64 + 4 > file_size {
+        bail!("invalid footer size {}", meta_size);
+    }
+    let data_end = file_size - 4 - meta_size as u64;
+    file.seek(SeekFrom::Start(data_end))?;
+    let mut footer = vec![0u8; meta_size as usize];
+    file.read_exact(&mut footer)?;
+    let raw_meta: RawMeta = serde_json::from_slice(&footer).context("failed to parse footer")?;
+    if raw_meta.max_value_per_block <= 0 {
+        bail!("max values per block should be positive");
+    }
+    Ok((raw_meta, data_end))
+}
+/// 把数据区和新的footer写入out_path
+fn write_repaired(
+    file: &mut File,
+    data_end: u64,
+    meta: &DB721Meta,
+    out_path: &Path,
+) -> anyhow::Result<()> {
+    let mut out = BufWriter::new(
+        File::create(out_path)
+            .with_context(|| format!("failed to create {}", out_path.display()))?,
+    );
+    file.seek(SeekFrom::Start(0))?;
+    std::io::copy(&mut file.take(data_end), &mut out)?;
+    write_footer(&mut out, meta)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
+/// 重新计算path的footer。out_path为空时与DB721Writer一样先写入`{path}.tmp`，再重命名覆盖path，
+/// 否则把数据和新的footer写入out_path
+pub fn repair_file(path: &Path, out_path: Option<&Path>) -> anyhow::Result<DB721Meta> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let (raw_meta, data_end) = read_raw_meta(&mut file)?;
+    let mut columns: Vec<(&String, &RawColumnMeta)> = raw_meta.column_meta.iter().collect();
+    columns.sort_by_key(|(_, column_meta)| column_meta.start_offset);
+
+    let max_values_per_block = raw_meta.max_value_per_block as usize;
+    let mut row_count: Option<usize> = None;
+    let mut column_meta = HashMap::new();
+    for (idx, (column_name, raw_column)) in columns.iter().enumerate() {
+        let value_type = raw_column.value_type();
+        let Some(width) = value_width(&value_type) else {
+            bail!("column {} has unsupported type {}", column_name, value_type);
+        };
+        // 压缩后的block大小只记录在block_stats中，无法重新切分
+        if let Some(codec) = &raw_column.codec {
+            if Codec::from_name(codec)? != Codec::None {
+                bail!(
+                    "column {} is compressed, which cannot be repaired",
+                    column_name
+                );
+            }
+        }
+        // 编码后的block以及带validity bitmap的block大小取决于block统计信息，同样无法重新切分
+        let encoded = raw_column.block_stats.values().any(|block_stats| {
+            block_stats
+                .get("encoding")
+                .is_some_and(|encoding| encoding != "plain")
+        });
+        if encoded {
+            bail!(
+                "column {} has encoded blocks, which cannot be repaired",
+                column_name
+            );
+        }
+        let nullable = raw_column.block_stats.values().any(|block_stats| {
+            block_stats
+                .get("null_count")
+                .and_then(|null_count| null_count.as_i64())
+                .is_some_and(|null_count| null_count > 0)
+        });
+        if nullable {
+            bail!(
+                "column {} has blocks with NULLs, which cannot be repaired",
+                column_name
+            );
+        }
+        // 原来有bloom filter的列按实际数据重新生成，不支持的类型无法生成
+        let rebuild_bloom = bloom::supports(&value_type)
+            && raw_column
+                .block_stats
+                .values()
+                .any(|block_stats| block_stats.get("bloom").is_some());
+        let start = raw_column.start_offset as u64;
+        let end = columns
+            .get(idx + 1)
+            .map_or(data_end, |(_, next_column)| next_column.start_offset as u64);
+        if raw_column.start_offset < 0 || end < start || end > data_end {
+            bail!("column {} has an invalid start_offset", column_name);
+        }
+        if !(end - start).is_multiple_of(width as u64) {
+            bail!(
+                "column {} is {} bytes, which is not a multiple of {}",
+                column_name,
+                end - start,
+                width
+            );
+        }
+        let values = ((end - start) / width as u64) as usize;
+        if row_count.is_some_and(|row_count| row_count != values) {
+            bail!(
+                "column {} has {} values, but the previous columns have {}",
+                column_name,
+                values,
+                row_count.unwrap()
+            );
+        }
+        row_count = Some(values);
+        let mut block_meta = HashMap::new();
+        let mut offset = start as usize;
+        for (block_idx, first_value) in (0..values).step_by(max_values_per_block).enumerate() {
+            let value_num = max_values_per_block.min(values - first_value);
+            let stored = BlockMeta {
+                value_num: value_num as i32,
+                ..BlockStats::default().finish(&value_type)
+            };
+            let (meta, invalid_utf8) = scan_block(
+                path,
+                &value_type,
+                Codec::None,
+                offset,
+                &stored,
+                rebuild_bloom,
+            )
+            .with_context(|| format!("failed to scan column {}", column_name))?;
+            if invalid_utf8 > 0 {
+                bail!(
+                    "column {} block {} has strings that are not valid UTF-8",
+                    column_name,
+                    block_idx
+                );
+            }
+            block_meta.insert(block_idx.to_string(), meta);
+            offset += value_num * width;
+        }
+        column_meta.insert(
+            column_name.to_string(),
+            ColumnMeta::new(&value_type, raw_column.start_offset, block_meta, None),
+        );
+    }
+    let meta = DB721Meta {
+        table_name: raw_meta.table_name.clone(),
+        max_value_per_block: raw_meta.max_value_per_block,
+        column_meta,
+    };
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    let target = out_path.unwrap_or(&tmp_path);
+    if let Err(e) = write_repaired(&mut file, data_end, &meta, target) {
+        // 写到一半的文件没有用处
+        let _ = std::fs::remove_file(target);
+        return Err(e);
+    }
+    if out_path.is_none() {
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to rename to {}", path.display()))?;
+    }
+    Ok(meta)
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::check::check_file;
+    use crate::db721::predicate::{CompareOp, Predicate};
+    use crate::db721::repair::repair_file;
+    use crate::db721::writer::test_util::write_test_file;
+    use crate::db721::{DB721Type, DB721};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_repair_missing_block_stats() {
+        let rows = [(1, "MALE"), (2, "FEMALE"), (3, "MALE")].map(|(id, sex)| {
+            [
+                Some(DB721Type::Integer(id)),
+                Some(DB721Type::Str(String::from(sex))),
+            ]
+        });
+        let file = write_test_file(
+            "repair_missing_block_stats",
+            2,
+            &[("identifier", "int"), ("sex", "str")],
+            rows,
+            |_| {},
+        );
+        let path = file.path.clone();
+        let out_path = std::env::temp_dir().join("db721_repair_missing_block_stats.out.db721");
+        let expected = file.open();
+        // 只保留表名、block大小以及各列的类型和偏移
+        let mut data = std::fs::read(&path).unwrap();
+        data.truncate(data.len() - expected.meta_size as usize - 4);
+        let footer = r#"{"Table": "Chicken", "Max Values Per Block": 2, "Columns": {
+            "identifier": {"type": "int", "start_offset": 0},
+            "sex": {"type": "str", "start_offset": 12}}}"#;
+        data.extend_from_slice(footer.as_bytes());
+        data.extend_from_slice(&(footer.len() as i32).to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert!(DB721::open(path.clone()).is_err());
+
+        repair_file(&path, Some(&out_path)).unwrap();
+        assert!(check_file(out_path.clone()).is_empty());
+        repair_file(&path, None).unwrap();
+        assert!(!PathBuf::from(format!("{}.tmp", path.display())).exists());
+        assert!(check_file(path.clone()).is_empty());
+        let repaired = DB721::open(path.clone()).unwrap();
+        let sex_meta = repaired.meta.column_meta.get("sex").unwrap();
+        assert_eq!(sex_meta.num_blocks(), 2);
+        assert_eq!(
+            sex_meta.block_meta(0).unwrap().min(),
+            &DB721Type::Str(String::from("FEMALE"))
+        );
+        assert_eq!(sex_meta.block_meta(0).unwrap().max_len(), Some(6));
+        assert_eq!(repaired.row_count(), expected.row_count());
+        std::fs::remove_file(out_path).unwrap();
+    }
+    #[test]
+    fn test_repair_rebuilds_bloom_filter() {
+        let rows = (0..10).map(|id| [Some(DB721Type::Integer(id * 2))]);
+        let file = write_test_file(
+            "repair_bloom_filter",
+            4,
+            &[("identifier", "int")],
+            rows,
+            |writer| {
+                writer
+                    .set_bloom_columns(&[String::from("identifier")])
+                    .unwrap();
+            },
+        );
+        let path = file.path.clone();
+        let expected = file.open();
+        // 每个block只保留bloom filter，其余统计信息缺失
+        let mut data = std::fs::read(&path).unwrap();
+        data.truncate(data.len() - expected.meta_size as usize - 4);
+        let mut footer: serde_json::Value = serde_json::to_value(&expected.meta).unwrap();
+        let block_stats = footer["Columns"]["identifier"]["block_stats"]
+            .as_object_mut()
+            .unwrap();
+        for block_stats in block_stats.values_mut() {
+            block_stats
+                .as_object_mut()
+                .unwrap()
+                .retain(|key, _| key == "bloom");
+        }
+        let footer = serde_json::to_vec(&footer).unwrap();
+        data.extend_from_slice(&footer);
+        data.extend_from_slice(&(footer.len() as i32).to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert!(DB721::open(path.clone()).is_err());
+
+        repair_file(&path, None).unwrap();
+        assert!(check_file(path.clone()).is_empty());
+        let repaired = DB721::open(path.clone()).unwrap();
+        let identifier_meta = &repaired.meta.column_meta["identifier"];
+        assert_eq!(identifier_meta.num_blocks(), 3);
+        for block_idx in 0..3 {
+            assert!(identifier_meta
+                .block_meta(block_idx)
+                .unwrap()
+                .bloom()
+                .is_some());
+        }
+        // 每个值所在的block都不会被bloom filter排除
+        for id in 0..10 {
+            let eq = Predicate::new(
+                String::from("identifier"),
+                CompareOp::Eq,
+                DB721Type::Integer(id * 2),
+            );
+            assert!(repaired.matching_blocks(&[eq]).contains(&(id / 4)));
+        }
+    }
+}
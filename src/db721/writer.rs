@@ -0,0 +1,1190 @@
+//! 生成db721文件。按行写入，每列的数据先按block编码后写到各自的临时文件，
+//! finish时按列拼接到同一目录下的临时文件并写入footer，最后重命名为目标文件，
+//! 失败或中途取消时不会留下写了一半的目标文件
+use crate::db721::binary::{self, Binary};
+use crate::db721::bloom::{self, BloomFilter};
+use crate::db721::decimal::{self, Decimal};
+use crate::db721::{
+    dict_code_width, encoding, value_width, BlockMeta, Codec, ColumnMeta, DB721Meta, DB721Type,
+    Encoding, DICT_MAX_SIZE,
+};
+use anyhow::{bail, Context};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// 定长字符串的宽度，block中有更长的值时该block改用var布局
+pub const STR_WIDTH: usize = 32;
+/// 统计一个block的num、null_count、min、max以及字符串的min_len、max_len，
+/// min、max等只统计不是NULL的值
+#[derive(Default)]
+pub(crate) struct BlockStats {
+    value_num: i32,
+    null_count: i32,
+    min: Option<DB721Type>,
+    max: Option<DB721Type>,
+    min_len: Option<i32>,
+    max_len: Option<i32>,
+    has_nan: bool,
+}
+impl BlockStats {
+    pub(crate) fn update(&mut self, value: &DB721Type) {
+        self.value_num += 1;
+        // NaN无法参与比较，也无法写入JSON，只记录block中有NaN
+        let is_nan = match value {
+            DB721Type::Float(val) => val.is_nan(),
+            DB721Type::Double(val) => val.is_nan(),
+            _ => false,
+        };
+        if is_nan {
+            self.has_nan = true;
+            return;
+        }
+        if let DB721Type::Str(val) = value {
+            let len = val.len() as i32;
+            self.min_len = Some(self.min_len.map_or(len, |min_len| min_len.min(len)));
+            self.max_len = Some(self.max_len.map_or(len, |max_len| max_len.max(len)));
+        }
+        if self.min.as_ref().is_none_or(|min| value < min) {
+            self.min = Some(value.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| value > max) {
+            self.max = Some(value.clone());
+        }
+    }
+    pub(crate) fn update_null(&mut self) {
+        self.value_num += 1;
+        self.null_count += 1;
+    }
+    pub(crate) fn finish(self, value_type: &str) -> BlockMeta {
+        let zero = match value_type {
+            "int" => DB721Type::Integer(0),
+            "bigint" => DB721Type::BigInt(0),
+            "double" => DB721Type::Double(0.0),
+            "float" => DB721Type::Float(0.0),
+            "bool" => DB721Type::Bool(false),
+            "date" => DB721Type::Date(0),
+            "timestamp" => DB721Type::Timestamp(0),
+            value_type => match (decimal::params(value_type), binary::params(value_type)) {
+                (Some((_, scale)), _) => DB721Type::Decimal(Decimal::new(0, scale)),
+                (_, Some(width)) => DB721Type::Binary(Binary::new(vec![0; width])),
+                _ => DB721Type::Str(String::new()),
+            },
+        };
+        BlockMeta {
+            value_num: self.value_num,
+            min: self.min.unwrap_or_else(|| zero.clone()),
+            max: self.max.unwrap_or(zero),
+            min_len: self.min_len,
+            max_len: self.max_len,
+            compressed_size: None,
+            encoding: None,
+            dictionary: None,
+            encoded_size: None,
+            null_count: (self.null_count > 0).then_some(self.null_count),
+            has_nan: self.has_nan.then_some(true),
+            bloom: None,
+        }
+    }
+}
+struct ColumnWriter {
+    column_name: String,
+    value_type: String,
+    spill_path: PathBuf,
+    spill: BufWriter<File>,
+    spill_size: u64,
+    codec: Codec,
+    auto_encoding: bool,
+    var_str: bool,
+    /// 当前block编码后的数据，写满一个block后压缩并写入临时文件
+    block_buf: Vec<u8>,
+    /// str列当前block的值，block写满时再按布局编码到block_buf
+    block_strs: Vec<String>,
+    /// 当前block每行是否不是NULL
+    block_validity: Vec<bool>,
+    /// 当前block中不同取值的哈希，不生成bloom filter的列为None
+    bloom_hashes: Option<HashSet<u64>>,
+    block_metas: Vec<BlockMeta>,
+    block_stats: BlockStats,
+}
+impl ColumnWriter {
+    fn write_value(&mut self, value: &DB721Type) -> anyhow::Result<()> {
+        let value = value.clone().coerce(self.value_type.as_str());
+        match (self.value_type.as_str(), &value) {
+            ("str", DB721Type::Str(val)) => {
+                self.update_stats(&value);
+                self.block_strs.push(val.clone());
+            }
+            (value_type, DB721Type::Decimal(val)) if decimal::params(value_type).is_some() => {
+                // coerce无法无损转换到列的scale时保持原scale
+                let (precision, scale) = decimal::params(value_type).unwrap();
+                if val.scale() != scale || !val.fits(precision) {
+                    bail!(
+                        "value {} cannot be stored as type {} of column {}",
+                        val,
+                        value_type,
+                        self.column_name
+                    );
+                }
+                let bytes = value.to_le_bytes();
+                self.block_buf
+                    .extend_from_slice(&bytes[..decimal::width(precision)]);
+                self.update_stats(&value);
+            }
+            (value_type, DB721Type::Binary(val))
+                if binary::params(value_type) == Some(val.len()) =>
+            {
+                self.block_buf.extend_from_slice(val.as_bytes());
+                self.update_stats(&value);
+            }
+            (value_type, _) if value.value_type() == value_type => {
+                self.block_buf.extend_from_slice(&value.to_le_bytes());
+                self.update_stats(&value);
+            }
+            _ => bail!(
+                "value {:?} does not match type {} of column {}",
+                value,
+                self.value_type,
+                self.column_name
+            ),
+        }
+        self.block_validity.push(true);
+        Ok(())
+    }
+    /// 更新block统计信息，需要bloom filter时记录值的哈希
+    fn update_stats(&mut self, value: &DB721Type) {
+        self.block_stats.update(value);
+        if let Some(hashes) = self.bloom_hashes.as_mut() {
+            hashes.extend(bloom::hash_value(value));
+        }
+    }
+    /// NULL行重复block中的上一个值作为占位，不影响rle、delta和字典编码的效果
+    fn write_null(&mut self) {
+        if self.value_type == "str" {
+            let placeholder = self.block_strs.last().cloned().unwrap_or_default();
+            self.block_strs.push(placeholder);
+        } else {
+            let width = value_width(self.value_type.as_str()).unwrap();
+            let placeholder = match self.block_buf.len() {
+                0 => vec![0; width],
+                len => self.block_buf[len - width..].to_vec(),
+            };
+            self.block_buf.extend_from_slice(&placeholder);
+        }
+        self.block_stats.update_null();
+        self.block_validity.push(false);
+    }
+    fn finish_block(&mut self) -> anyhow::Result<()> {
+        if self.block_stats.value_num == 0 {
+            return Ok(());
+        }
+        let block_stats = std::mem::take(&mut self.block_stats);
+        let mut block_meta = block_stats.finish(self.value_type.as_str());
+        if self.value_type == "str" {
+            self.encode_strs(&mut block_meta);
+        }
+        if self.auto_encoding && self.value_type == "int" {
+            self.int_encode(&mut block_meta);
+        }
+        // 全是NULL的block不需要bloom filter
+        if let Some(hashes) = self
+            .bloom_hashes
+            .as_mut()
+            .filter(|hashes| !hashes.is_empty())
+        {
+            let mut bloom = BloomFilter::with_capacity(hashes.len());
+            for hash in hashes.drain() {
+                bloom.insert_hash(hash);
+            }
+            block_meta.bloom = Some(bloom);
+        }
+        if block_meta.null_count() > 0 {
+            let mut data = vec![0u8; block_meta.validity_size()];
+            for (idx, valid) in self.block_validity.iter().enumerate() {
+                if *valid {
+                    data[idx / 8] |= 1 << (idx % 8);
+                }
+            }
+            data.extend_from_slice(&self.block_buf);
+            self.block_buf = data;
+        }
+        self.block_validity.clear();
+        match self.codec {
+            Codec::None => {
+                self.spill.write_all(&self.block_buf)?;
+                self.spill_size += self.block_buf.len() as u64;
+            }
+            Codec::Lz4 => {
+                let compressed = lz4::block::compress(&self.block_buf, None, false)?;
+                self.spill.write_all(&compressed)?;
+                self.spill_size += compressed.len() as u64;
+                block_meta.compressed_size = Some(compressed.len() as i32);
+            }
+        }
+        self.block_buf.clear();
+        self.block_metas.push(block_meta);
+        Ok(())
+    }
+    /// 按定长或var布局把block_strs写入block_buf，开启自动编码时再尝试字典编码。
+    /// 有值超过STR_WIDTH字节时，即使没有开启var_str，这个block也使用var布局
+    fn encode_strs(&mut self, block_meta: &mut BlockMeta) {
+        let strs = std::mem::take(&mut self.block_strs);
+        let var_layout = self.var_str || strs.iter().any(|value| value.len() > STR_WIDTH);
+        if var_layout {
+            self.block_buf = encoding::var_encode(&strs);
+            block_meta.encoding = Some(Encoding::Var.name().to_string());
+            block_meta.encoded_size = Some(self.block_buf.len() as i32);
+        } else {
+            for value in strs.iter() {
+                let mut buf = [0u8; STR_WIDTH];
+                buf[..value.len()].copy_from_slice(value.as_bytes());
+                self.block_buf.extend_from_slice(&buf);
+            }
+        }
+        if self.auto_encoding {
+            self.dict_encode(&strs, var_layout, block_meta);
+        }
+    }
+    /// 不同取值足够少、字典编码更小时，把block_buf替换为字典下标
+    fn dict_encode(&mut self, strs: &[String], var_layout: bool, block_meta: &mut BlockMeta) {
+        let mut dictionary: Vec<&str> = Vec::new();
+        let mut code_of: HashMap<&str, u16> = HashMap::new();
+        let mut codes = Vec::with_capacity(strs.len());
+        for value in strs {
+            let code = match code_of.get(value.as_str()) {
+                Some(code) => *code,
+                None => {
+                    if dictionary.len() == DICT_MAX_SIZE {
+                        return;
+                    }
+                    let code = dictionary.len() as u16;
+                    dictionary.push(value);
+                    code_of.insert(value, code);
+                    code
+                }
+            };
+            codes.push(code);
+        }
+        let code_width = dict_code_width(dictionary.len());
+        // 字典本身写在footer中，也计入大小，每一项按当前布局中一个值的大小计算
+        let dictionary_size: usize = if var_layout {
+            dictionary.iter().map(|value| value.len() + 4).sum()
+        } else {
+            dictionary.len() * STR_WIDTH
+        };
+        if dictionary_size + strs.len() * code_width >= self.block_buf.len() {
+            return;
+        }
+        let mut data = Vec::with_capacity(strs.len() * code_width);
+        for code in codes {
+            if code_width == 1 {
+                data.push(code as u8);
+            } else {
+                data.extend_from_slice(&code.to_le_bytes());
+            }
+        }
+        block_meta.encoding = Some(Encoding::Dict.name().to_string());
+        block_meta.encoded_size = None;
+        block_meta.dictionary = Some(dictionary.into_iter().map(str::to_string).collect());
+        self.block_buf = data;
+    }
+    /// rle和delta中更小的一种比定长数据小时，用它替换block_buf
+    fn int_encode(&mut self, block_meta: &mut BlockMeta) {
+        let values: Vec<i32> = self
+            .block_buf
+            .chunks_exact(4)
+            .map(|chunk| i32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let (encoding, data) = [
+            (Encoding::Rle, encoding::rle_encode(&values)),
+            (Encoding::Delta, encoding::delta_encode(&values)),
+        ]
+        .into_iter()
+        .min_by_key(|(_, data)| data.len())
+        .unwrap();
+        if data.len() >= self.block_buf.len() {
+            return;
+        }
+        block_meta.encoding = Some(encoding.name().to_string());
+        block_meta.encoded_size = Some(data.len() as i32);
+        self.block_buf = data;
+    }
+}
+pub struct DB721Writer {
+    path: PathBuf,
+    /// finish时先写入该文件，完成后重命名为path
+    tmp_path: PathBuf,
+    table_name: String,
+    max_values_per_block: i32,
+    columns: Vec<ColumnWriter>,
+    rows_in_block: i32,
+    row_count: usize,
+}
+impl DB721Writer {
+    /// columns为(列名, 类型)，类型为 int / bigint / float / double / bool / date / timestamp / str
+    pub fn create(
+        path: PathBuf,
+        table_name: String,
+        max_values_per_block: i32,
+        columns: Vec<(String, String)>,
+    ) -> anyhow::Result<Self> {
+        if max_values_per_block <= 0 {
+            bail!("max values per block should be positive");
+        }
+        let mut column_writers = Vec::with_capacity(columns.len());
+        for (idx, (column_name, value_type)) in columns.into_iter().enumerate() {
+            if value_width(value_type.as_str()).is_none() {
+                bail!("no support for value type = {}", value_type);
+            }
+            if column_writers
+                .iter()
+                .any(|column: &ColumnWriter| column.column_name == column_name)
+            {
+                bail!("duplicate column {}", column_name);
+            }
+            let spill_path = PathBuf::from(format!("{}.col{}.tmp", path.display(), idx));
+            let spill = BufWriter::new(
+                File::create(&spill_path)
+                    .with_context(|| format!("failed to create {}", spill_path.display()))?,
+            );
+            column_writers.push(ColumnWriter {
+                column_name,
+                value_type,
+                spill_path,
+                spill,
+                spill_size: 0,
+                codec: Codec::None,
+                auto_encoding: false,
+                var_str: false,
+                block_buf: Vec::new(),
+                block_strs: Vec::new(),
+                block_validity: Vec::new(),
+                bloom_hashes: None,
+                block_metas: Vec::new(),
+                block_stats: BlockStats::default(),
+            });
+        }
+        Ok(Self {
+            tmp_path: PathBuf::from(format!("{}.tmp", path.display())),
+            path,
+            table_name,
+            max_values_per_block,
+            columns: column_writers,
+            rows_in_block: 0,
+            row_count: 0,
+        })
+    }
+    /// 设置所有列的压缩方式，需要在写入第一行之前调用
+    pub fn set_codec(&mut self, codec: Codec) -> &mut Self {
+        debug_assert_eq!(self.row_count, 0);
+        for column in self.columns.iter_mut() {
+            column.codec = codec;
+        }
+        self
+    }
+    /// 按block选择更紧凑的编码（str列为dict，int列为rle或delta），需要在写入第一行之前调用
+    pub fn set_auto_encoding(&mut self, auto_encoding: bool) -> &mut Self {
+        debug_assert_eq!(self.row_count, 0);
+        for column in self.columns.iter_mut() {
+            column.auto_encoding = auto_encoding;
+        }
+        self
+    }
+    /// str列的所有block都使用var布局，需要在写入第一行之前调用
+    pub fn set_var_str(&mut self, var_str: bool) -> &mut Self {
+        debug_assert_eq!(self.row_count, 0);
+        for column in self.columns.iter_mut() {
+            column.var_str = var_str;
+        }
+        self
+    }
+    /// 为指定的列生成每个block的bloom filter，用于等值查询跳过block，需要在写入第一行之前调用。
+    /// 只支持int、bigint、date、timestamp、str、decimal和binary列
+    pub fn set_bloom_columns(&mut self, column_names: &[String]) -> anyhow::Result<&mut Self> {
+        debug_assert_eq!(self.row_count, 0);
+        for column_name in column_names {
+            let Some(column) = self
+                .columns
+                .iter_mut()
+                .find(|column| &column.column_name == column_name)
+            else {
+                bail!("column {} does not exist", column_name);
+            };
+            if !bloom::supports(&column.value_type) {
+                bail!(
+                    "bloom filter is not supported for column {} of type {}",
+                    column_name,
+                    column.value_type
+                );
+            }
+            column.bloom_hashes = Some(HashSet::new());
+        }
+        Ok(self)
+    }
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+    pub fn write_row(&mut self, row: &[DB721Type]) -> anyhow::Result<()> {
+        self.write_values(row.iter().map(Some))
+    }
+    /// 写入可能包含NULL的一行，None表示NULL
+    pub fn write_nullable_row(&mut self, row: &[Option<DB721Type>]) -> anyhow::Result<()> {
+        self.write_values(row.iter().map(Option::as_ref))
+    }
+    fn write_values<'a>(
+        &mut self,
+        row: impl ExactSizeIterator<Item = Option<&'a DB721Type>>,
+    ) -> anyhow::Result<()> {
+        if row.len() != self.columns.len() {
+            bail!(
+                "row has {} values, but table has {} columns",
+                row.len(),
+                self.columns.len()
+            );
+        }
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            match value {
+                Some(value) => column.write_value(value)?,
+                None => column.write_null(),
+            }
+        }
+        self.row_count += 1;
+        self.rows_in_block += 1;
+        if self.rows_in_block == self.max_values_per_block {
+            for column in self.columns.iter_mut() {
+                column.finish_block()?;
+            }
+            self.rows_in_block = 0;
+        }
+        Ok(())
+    }
+    /// 拼接各列数据并写入footer，返回写入的元信息
+    pub fn finish(mut self) -> anyhow::Result<DB721Meta> {
+        let mut file = BufWriter::new(
+            File::create(&self.tmp_path)
+                .with_context(|| format!("failed to create {}", self.tmp_path.display()))?,
+        );
+        let mut offset: u64 = 0;
+        let mut column_meta = HashMap::new();
+        for column in self.columns.iter_mut() {
+            column.finish_block()?;
+            column.spill.flush()?;
+            let mut spill = File::open(&column.spill_path)?;
+            std::io::copy(&mut spill, &mut file)?;
+            let block_meta: HashMap<String, BlockMeta> = column
+                .block_metas
+                .iter()
+                .enumerate()
+                .map(|(idx, block_meta)| (idx.to_string(), block_meta.clone()))
+                .collect();
+            let codec = match column.codec {
+                Codec::None => None,
+                codec => Some(codec.name().to_string()),
+            };
+            column_meta.insert(
+                column.column_name.clone(),
+                ColumnMeta::new(&column.value_type, offset as i32, block_meta, codec),
+            );
+            offset += column.spill_size;
+        }
+        let meta = DB721Meta {
+            table_name: self.table_name.clone(),
+            max_value_per_block: self.max_values_per_block,
+            column_meta,
+        };
+        write_footer(&mut file, &meta)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("failed to rename to {}", self.path.display()))?;
+        Ok(meta)
+    }
+}
+/// 写入JSON footer及其长度
+pub(crate) fn write_footer(out: &mut impl Write, meta: &DB721Meta) -> anyhow::Result<()> {
+    let footer = serde_json::to_vec(meta)?;
+    out.write_all(&footer)?;
+    out.write_all(&(footer.len() as i32).to_le_bytes())?;
+    Ok(())
+}
+impl Drop for DB721Writer {
+    /// 无论成功与否都删除临时文件
+    fn drop(&mut self) {
+        for column in self.columns.iter() {
+            let _ = std::fs::remove_file(&column.spill_path);
+        }
+        // finish成功时已经重命名，不存在
+        let _ = std::fs::remove_file(&self.tmp_path);
+    }
+}
+/// 测试中生成db721文件的辅助函数
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::db721::check::check_file;
+    use crate::db721::writer::{write_footer, DB721Writer};
+    use crate::db721::{
+        ColumnCursor, ColumnIterator, ColumnIteratorBuilder, DB721Meta, DB721Type, DB721,
+    };
+    use std::path::{Path, PathBuf};
+
+    /// 临时目录下的测试文件，drop时删除
+    pub(crate) struct TestFile {
+        pub(crate) path: PathBuf,
+        /// finish返回的footer
+        pub(crate) meta: DB721Meta,
+    }
+    impl TestFile {
+        pub(crate) fn open(&self) -> DB721 {
+            DB721::open(self.path.clone()).unwrap()
+        }
+    }
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+    /// 在临时目录下写入 `db721_<name>.db721`，configure在写入第一行之前设置writer，
+    /// rows中的None为NULL。写完后检查文件没有问题
+    pub(crate) fn write_test_file<R: AsRef<[Option<DB721Type>]>>(
+        name: &str,
+        max_values_per_block: i32,
+        columns: &[(&str, &str)],
+        rows: impl IntoIterator<Item = R>,
+        configure: impl FnOnce(&mut DB721Writer),
+    ) -> TestFile {
+        let path = std::env::temp_dir().join(format!("db721_{}.db721", name));
+        let columns = columns
+            .iter()
+            .map(|(column_name, value_type)| (column_name.to_string(), value_type.to_string()))
+            .collect();
+        let mut writer = DB721Writer::create(
+            path.clone(),
+            name.to_string(),
+            max_values_per_block,
+            columns,
+        )
+        .unwrap();
+        configure(&mut writer);
+        for row in rows {
+            writer.write_nullable_row(row.as_ref()).unwrap();
+        }
+        let meta = writer.finish().unwrap();
+        assert!(check_file(path.clone()).is_empty());
+        TestFile { path, meta }
+    }
+    /// 用meta替换文件原有的footer
+    pub(crate) fn rewrite_footer(path: &Path, meta: &DB721Meta) {
+        let meta_size = DB721::open(path.to_path_buf()).unwrap().meta_size;
+        let mut data = std::fs::read(path).unwrap();
+        data.truncate(data.len() - meta_size as usize - 4);
+        write_footer(&mut data, meta).unwrap();
+        std::fs::write(path, &data).unwrap();
+    }
+    /// 从第一行开始读取一列
+    pub(crate) fn column_iterator(db721: &DB721, column_name: &str) -> ColumnIterator {
+        ColumnIteratorBuilder::new(
+            db721.meta.column_meta[column_name].clone(),
+            column_name.to_string(),
+            db721.path.clone(),
+        )
+        .build()
+        .unwrap()
+    }
+    pub(crate) fn column_cursor(db721: &DB721, column_name: &str) -> ColumnCursor {
+        ColumnCursor::new(column_iterator(db721, column_name))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::db721::binary::Binary;
+    use crate::db721::predicate::{CompareOp, Predicate};
+    use crate::db721::writer::test_util::{column_cursor, column_iterator, write_test_file};
+    use crate::db721::writer::DB721Writer;
+    use crate::db721::{Codec, ColumnCursor, DB721Type, Encoding, DB721};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_writer_round_trip() {
+        let rows =
+            [(3, 1.5f32, "MALE"), (1, 2.5, "FEMALE"), (2, 0.5, "MALE")].map(|(id, weight, sex)| {
+                [
+                    Some(DB721Type::Integer(id)),
+                    Some(DB721Type::Float(weight)),
+                    Some(DB721Type::Str(String::from(sex))),
+                ]
+            });
+        let file = write_test_file(
+            "writer_round_trip",
+            2,
+            &[("identifier", "int"), ("weight_g", "float"), ("sex", "str")],
+            rows,
+            |_| {},
+        );
+
+        let db721 = file.open();
+        assert_eq!(db721.row_count(), 3);
+        let id_meta = db721.meta.column_meta.get("identifier").unwrap();
+        assert_eq!(id_meta.num_blocks(), 2);
+        assert_eq!(id_meta.block_meta(0).unwrap().min(), &DB721Type::Integer(1));
+        assert_eq!(id_meta.block_meta(0).unwrap().max(), &DB721Type::Integer(3));
+        let sex_meta = db721.meta.column_meta.get("sex").unwrap();
+        assert_eq!(sex_meta.block_meta(0).unwrap().min_len(), Some(4));
+        let mut sex_cursor = column_cursor(&db721, "sex");
+        let mut sexes = Vec::new();
+        while let Some((batch, value_idx)) = sex_cursor.advance() {
+            sexes.push(batch.str_at(value_idx).to_string());
+        }
+        assert_eq!(sexes, vec!["MALE", "FEMALE", "MALE"]);
+    }
+    #[test]
+    fn test_writer_replaces_target_on_finish() {
+        let path = std::env::temp_dir().join("db721_writer_replace.db721");
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        std::fs::write(&path, b"old").unwrap();
+        let create = || {
+            let mut writer = DB721Writer::create(
+                path.clone(),
+                String::from("Chicken"),
+                2,
+                vec![(String::from("identifier"), String::from("int"))],
+            )
+            .unwrap();
+            writer.write_row(&[DB721Type::Integer(1)]).unwrap();
+            writer
+        };
+        // 没有finish的writer不会改动已有的文件
+        drop(create());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        create().finish().unwrap();
+        assert!(!tmp_path.exists());
+        assert_eq!(DB721::open(path.clone()).unwrap().row_count(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_writer_lz4() {
+        let rows = (0..2500).map(|id| {
+            [
+                Some(DB721Type::Integer(id)),
+                Some(DB721Type::Str(String::from("Cheep Birds"))),
+            ]
+        });
+        let file = write_test_file(
+            "writer_lz4",
+            1000,
+            &[("identifier", "int"), ("farm_name", "str")],
+            rows,
+            |writer| {
+                writer.set_codec(Codec::Lz4);
+            },
+        );
+
+        let db721 = file.open();
+        let farm_meta = db721.meta.column_meta.get("farm_name").unwrap();
+        assert_eq!(farm_meta.codec(), Codec::Lz4);
+        assert!(farm_meta.block_meta(0).unwrap().compressed_size().unwrap() < 1000 * 32);
+        let mut id_iter = column_iterator(&db721, "identifier");
+        for id in 0..2500 {
+            assert_eq!(id_iter.next(), Some(DB721Type::Integer(id)));
+        }
+        assert_eq!(id_iter.next(), None);
+    }
+    #[test]
+    fn test_writer_dict_encoding() {
+        // 第一个block只有FEMALE和UNKNOWN，第二个block中也有MALE
+        let rows = (0..200).map(|idx| {
+            let sex = match idx % 2 {
+                0 => "FEMALE",
+                _ if idx < 100 => "UNKNOWN",
+                _ => "MALE",
+            };
+            [Some(DB721Type::Str(String::from(sex)))]
+        });
+        let file = write_test_file(
+            "writer_dict_encoding",
+            100,
+            &[("sex", "str")],
+            rows,
+            |writer| {
+                writer.set_auto_encoding(true);
+            },
+        );
+
+        let db721 = file.open();
+        let sex_meta = db721.meta.column_meta.get("sex").unwrap();
+        let block_meta = sex_meta.block_meta(0).unwrap();
+        assert_eq!(block_meta.encoding(), Encoding::Dict);
+        assert_eq!(block_meta.encoded_size("str"), 100);
+        let male = Predicate::new(
+            String::from("sex"),
+            CompareOp::Eq,
+            DB721Type::Str(String::from("MALE")),
+        );
+        assert_eq!(db721.matching_blocks(&[male]), vec![1]);
+        let mut sex_iter = column_iterator(&db721, "sex");
+        assert_eq!(
+            sex_iter.next(),
+            Some(DB721Type::Str(String::from("FEMALE")))
+        );
+        assert_eq!(
+            sex_iter.next(),
+            Some(DB721Type::Str(String::from("UNKNOWN")))
+        );
+    }
+    #[test]
+    fn test_writer_int_encoding() {
+        let row = |id: i32| {
+            [
+                Some(DB721Type::Integer(id * 2)),
+                Some(DB721Type::Integer(id / 300)),
+                Some(DB721Type::Float(id as f32)),
+            ]
+        };
+        let file = write_test_file(
+            "writer_int_encoding",
+            1000,
+            &[("identifier", "int"), ("age", "int"), ("weight", "float")],
+            (0..2500).map(row),
+            |writer| {
+                writer.set_auto_encoding(true).set_codec(Codec::Lz4);
+            },
+        );
+
+        let db721 = file.open();
+        let block_meta = |column_name: &str| {
+            db721.meta.column_meta[column_name]
+                .block_meta(0)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(block_meta("identifier").encoding(), Encoding::Delta);
+        assert_eq!(block_meta("age").encoding(), Encoding::Rle);
+        assert_eq!(block_meta("weight").encoding(), Encoding::Plain);
+        // 跳到第二个block的中间，各列仍然按行对齐
+        let mut cursors: Vec<ColumnCursor> = ["identifier", "age", "weight"]
+            .into_iter()
+            .map(|column_name| column_cursor(&db721, column_name))
+            .collect();
+        for (idx, cursor) in cursors.iter_mut().enumerate() {
+            if idx == 0 {
+                cursor.advance();
+                cursor.skip(1499);
+            } else {
+                cursor.skip(1500);
+            }
+        }
+        for id in 1500..2500 {
+            let values: Vec<DB721Type> = cursors
+                .iter_mut()
+                .map(|cursor| {
+                    let (batch, value_idx) = cursor.advance().unwrap();
+                    batch.get(value_idx)
+                })
+                .collect();
+            assert_eq!(values, row(id).map(Option::unwrap));
+        }
+        assert!(cursors[0].advance().is_none());
+    }
+    #[test]
+    fn test_writer_var_str() {
+        let note = |idx: usize| format!("chicken {} has a note longer than 32 bytes", idx);
+        let sex = |idx: usize| if idx % 2 == 0 { "FEMALE" } else { "MALE" };
+        let rows = (0..250).map(|idx| {
+            [
+                Some(DB721Type::Str(note(idx))),
+                Some(DB721Type::Str(String::from(sex(idx)))),
+            ]
+        });
+        let file = write_test_file(
+            "writer_var_str",
+            100,
+            &[("notes", "str"), ("sex", "str")],
+            rows,
+            |writer| {
+                writer.set_var_str(true).set_auto_encoding(true);
+            },
+        );
+
+        let db721 = file.open();
+        let block_meta = db721.meta.column_meta["notes"].block_meta(0).unwrap();
+        assert_eq!(block_meta.encoding(), Encoding::Var);
+        assert_eq!(block_meta.min_len(), Some(note(0).len() as i32));
+        assert_eq!(block_meta.max_len(), Some(note(10).len() as i32));
+        let sex_meta = &db721.meta.column_meta["sex"];
+        assert_eq!(sex_meta.block_meta(0).unwrap().encoding(), Encoding::Dict);
+        let mut cursors: Vec<ColumnCursor> = ["notes", "sex"]
+            .into_iter()
+            .map(|column_name| {
+                let mut cursor = column_cursor(&db721, column_name);
+                cursor.skip(150);
+                cursor
+            })
+            .collect();
+        for idx in 150..250 {
+            let (batch, value_idx) = cursors[0].advance().unwrap();
+            assert_eq!(batch.str_at(value_idx), note(idx));
+            let (batch, value_idx) = cursors[1].advance().unwrap();
+            assert_eq!(batch.str_at(value_idx), sex(idx));
+        }
+        assert!(cursors[0].advance().is_none());
+    }
+    #[test]
+    fn test_writer_long_str_without_var_str() {
+        // 只有第二个block中有超过32字节的值
+        let name = |idx: usize| {
+            if idx == 150 {
+                format!("chicken {} has a name longer than 32 bytes", idx)
+            } else {
+                format!("chicken {}", idx)
+            }
+        };
+        let rows = (0..250).map(|idx| [Some(DB721Type::Str(name(idx)))]);
+        let file = write_test_file("writer_long_str", 100, &[("name", "str")], rows, |_| {});
+
+        let db721 = file.open();
+        let column_meta = &db721.meta.column_meta["name"];
+        assert_eq!(
+            column_meta.block_meta(0).unwrap().encoding(),
+            Encoding::Plain
+        );
+        let block_meta = column_meta.block_meta(1).unwrap();
+        assert_eq!(block_meta.encoding(), Encoding::Var);
+        assert_eq!(block_meta.max_len(), Some(name(150).len() as i32));
+        assert_eq!(
+            column_meta.block_meta(2).unwrap().encoding(),
+            Encoding::Plain
+        );
+        let mut cursor = column_cursor(&db721, "name");
+        for idx in 0..250 {
+            let (batch, value_idx) = cursor.advance().unwrap();
+            assert_eq!(batch.str_at(value_idx), name(idx));
+        }
+        assert!(cursor.advance().is_none());
+    }
+    #[test]
+    fn test_writer_nulls() {
+        // 第一个block没有NULL，第二个block部分为NULL，第三个block全是NULL
+        let age = |idx: i32| (idx < 100 || (idx < 200 && idx % 3 != 0)).then_some(idx / 10);
+        let notes = |idx: i32| (idx < 200 && idx % 5 != 0).then(|| format!("note {}", idx));
+        let rows = (0..250).map(|idx| {
+            [
+                age(idx).map(DB721Type::Integer),
+                notes(idx).map(DB721Type::Str),
+            ]
+        });
+        let file = write_test_file(
+            "writer_nulls",
+            100,
+            &[("age", "int"), ("notes", "str")],
+            rows,
+            |writer| {
+                writer.set_auto_encoding(true).set_var_str(true);
+            },
+        );
+
+        let db721 = file.open();
+        let age_meta = &db721.meta.column_meta["age"];
+        assert_eq!(age_meta.block_meta(0).unwrap().null_count(), 0);
+        assert_eq!(age_meta.block_meta(1).unwrap().null_count(), 33);
+        assert_eq!(
+            age_meta.block_meta(1).unwrap().min(),
+            &DB721Type::Integer(10)
+        );
+        assert!(age_meta.block_meta(2).unwrap().all_null());
+        let is_null = Predicate::new_null_test(String::from("age"), true);
+        assert_eq!(db721.matching_blocks(&[is_null]), vec![1, 2]);
+        let is_not_null = Predicate::new_null_test(String::from("age"), false);
+        assert_eq!(db721.matching_blocks(&[is_not_null]), vec![0, 1]);
+
+        let mut cursors: Vec<ColumnCursor> = ["age", "notes"]
+            .into_iter()
+            .map(|column_name| column_cursor(&db721, column_name))
+            .collect();
+        cursors[0].skip(50);
+        cursors[1].skip(50);
+        for idx in 50..250 {
+            let (batch, value_idx) = cursors[0].advance().unwrap();
+            assert_eq!(batch.value(value_idx), age(idx).map(DB721Type::Integer));
+            let (batch, value_idx) = cursors[1].advance().unwrap();
+            assert_eq!(batch.value(value_idx), notes(idx).map(DB721Type::Str));
+        }
+    }
+    #[test]
+    fn test_writer_wide_types() {
+        let row = |idx: i64| {
+            vec![
+                Some(DB721Type::BigInt(5_000_000_000 + idx)),
+                Some(DB721Type::Double(idx as f64 / 3.0)),
+                (idx % 7 != 0).then_some(DB721Type::Bool(idx % 2 == 0)),
+                Some(DB721Type::Date(19358 + idx as i32 / 100)),
+                Some(DB721Type::Timestamp(
+                    1_672_574_400_000_000 + idx * 1_000_001,
+                )),
+            ]
+        };
+        // int常量可以写入bigint列
+        let last_row = [
+            DB721Type::Integer(1),
+            DB721Type::Double(0.0),
+            DB721Type::Bool(true),
+            DB721Type::Date(0),
+            DB721Type::Timestamp(0),
+        ]
+        .map(Some)
+        .to_vec();
+        let file = write_test_file(
+            "writer_wide_types",
+            100,
+            &[
+                ("id", "bigint"),
+                ("value", "double"),
+                ("ok", "bool"),
+                ("day", "date"),
+                ("ts", "timestamp"),
+            ],
+            (0..250).map(row).chain([last_row]),
+            |writer| {
+                assert!(writer
+                    .write_row(&[
+                        DB721Type::Str(String::from("1")),
+                        DB721Type::Double(0.0),
+                        DB721Type::Bool(true),
+                        DB721Type::Date(0),
+                        DB721Type::Timestamp(0),
+                    ])
+                    .is_err());
+            },
+        );
+
+        let db721 = file.open();
+        let block_meta = |column_name: &str| {
+            db721.meta.column_meta[column_name]
+                .block_meta(1)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(block_meta("id").max(), &DB721Type::BigInt(5_000_000_199));
+        assert_eq!(block_meta("value").max(), &DB721Type::Double(199.0 / 3.0));
+        assert_eq!(block_meta("ok").min(), &DB721Type::Bool(false));
+        assert_eq!(block_meta("ok").null_count(), 14);
+        assert_eq!(block_meta("day").min(), &DB721Type::Date(19359));
+        assert_eq!(block_meta("day").min().to_string(), "2023-01-02");
+        assert_eq!(
+            block_meta("ts").min(),
+            &DB721Type::Timestamp(1_672_574_400_000_000 + 100 * 1_000_001)
+        );
+        let pred = Predicate::new(String::from("id"), CompareOp::Ge, DB721Type::Integer(1000));
+        assert_eq!(db721.matching_blocks(&[pred]), vec![0, 1, 2]);
+        let pred = Predicate::new(
+            String::from("id"),
+            CompareOp::Eq,
+            DB721Type::BigInt(5_000_000_050),
+        );
+        // 最后一个block中还有后写入的id为1的行
+        assert_eq!(db721.matching_blocks(&[pred]), vec![0, 2]);
+
+        for (column_idx, column_name) in ["id", "value", "ok", "day", "ts"].iter().enumerate() {
+            let mut cursor = column_cursor(&db721, column_name);
+            cursor.skip(90);
+            for idx in 90..250 {
+                let (batch, value_idx) = cursor.advance().unwrap();
+                assert_eq!(batch.value(value_idx), row(idx)[column_idx]);
+            }
+        }
+    }
+    #[test]
+    fn test_writer_nan() {
+        let rows = [1.0, f64::NAN, f64::NAN, f64::NAN, 2.0, 3.0]
+            .map(|value| [Some(DB721Type::Double(value))]);
+        let file = write_test_file("writer_nan", 2, &[("value", "double")], rows, |_| {});
+
+        let db721 = file.open();
+        let column_meta = &db721.meta.column_meta["value"];
+        let block_meta = column_meta.block_meta(0).unwrap();
+        assert!(block_meta.has_nan());
+        assert_eq!(block_meta.max(), &DB721Type::Double(1.0));
+        assert!(column_meta.block_meta(1).unwrap().has_nan());
+        assert!(!column_meta.block_meta(2).unwrap().has_nan());
+        // postgres中NaN大于所有数，有NaN的block不能按max跳过
+        let pred =
+            |op: CompareOp| Predicate::new(String::from("value"), op, DB721Type::Double(5.0));
+        assert!(pred(CompareOp::Gt).may_match_block(block_meta));
+        assert_eq!(db721.matching_blocks(&[pred(CompareOp::Gt)]), vec![0, 1]);
+        assert_eq!(db721.matching_blocks(&[pred(CompareOp::Ge)]), vec![0, 1]);
+        // 其它比较仍然按min/max跳过
+        assert!(db721.matching_blocks(&[pred(CompareOp::Eq)]).is_empty());
+    }
+    #[test]
+    fn test_writer_decimal() {
+        let decimal = |value: &str| DB721Type::Decimal(value.parse().unwrap());
+        let row = |idx: i64| {
+            vec![
+                Some(decimal(&format!("{}.{:02}", idx - 100, idx % 100))),
+                (idx % 5 != 0).then(|| decimal(&format!("{}12345678901234567890.0001", idx))),
+            ]
+        };
+        // 小数位数较少的值按列的scale写入
+        let last_row = vec![Some(DB721Type::Integer(7)), Some(decimal("1.5"))];
+        let file = write_test_file(
+            "writer_decimal",
+            100,
+            &[("amount", "decimal(12,2)"), ("total", "decimal(30,4)")],
+            (0..250).map(row).chain([last_row]),
+            |writer| {
+                // 超出精度或scale的值报错
+                assert!(writer.write_row(&[decimal("1.005"), decimal("1")]).is_err());
+                assert!(writer
+                    .write_row(&[decimal("10000000000"), decimal("1")])
+                    .is_err());
+            },
+        );
+
+        let db721 = file.open();
+        let amount_meta = &db721.meta.column_meta["amount"];
+        assert_eq!(amount_meta.value_type(), "decimal(12,2)");
+        assert_eq!(
+            db721.meta.column_meta["total"].value_type(),
+            "decimal(30,4)"
+        );
+        assert_eq!(amount_meta.block_meta(1).unwrap().min().to_string(), "0.00");
+        assert_eq!(amount_meta.block_meta(1).unwrap().max(), &decimal("99.99"));
+        let pred = Predicate::new(String::from("amount"), CompareOp::Lt, decimal("-0.5"));
+        assert_eq!(db721.matching_blocks(&[pred]), vec![0]);
+        let pred = Predicate::new(
+            String::from("amount"),
+            CompareOp::Eq,
+            DB721Type::Integer(120),
+        );
+        assert_eq!(db721.matching_blocks(&[pred]), vec![2]);
+
+        for (column_idx, column_name) in ["amount", "total"].iter().enumerate() {
+            let mut cursor = column_cursor(&db721, column_name);
+            for idx in 0..250 {
+                let (batch, value_idx) = cursor.advance().unwrap();
+                assert_eq!(batch.value(value_idx), row(idx)[column_idx]);
+            }
+        }
+    }
+    #[test]
+    fn test_writer_binary() {
+        let uuid = |idx: u32| {
+            let mut bytes = vec![0u8; 16];
+            // 大致随机的顺序，让每个block的min/max范围不同
+            bytes[..4].copy_from_slice(&idx.wrapping_mul(2654435761).to_be_bytes());
+            bytes[12..].copy_from_slice(&idx.to_be_bytes());
+            DB721Type::Binary(Binary::new(bytes))
+        };
+        // 十六进制字符串按列的宽度转换
+        let last_row = [Some(DB721Type::Str(String::from(
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11",
+        )))];
+        let file = write_test_file(
+            "writer_binary",
+            100,
+            &[("id", "binary(16)")],
+            (0..250).map(|idx| [Some(uuid(idx))]).chain([last_row]),
+            |writer| {
+                // 宽度不同的值报错
+                assert!(writer
+                    .write_row(&[DB721Type::Binary(Binary::new(vec![1, 2, 3]))])
+                    .is_err());
+            },
+        );
+
+        let db721 = file.open();
+        let id_meta = &db721.meta.column_meta["id"];
+        assert_eq!(id_meta.value_type(), "binary(16)");
+        assert_eq!(
+            id_meta.block_meta(0).unwrap().min(),
+            &DB721Type::Binary(Binary::new(vec![0; 16]))
+        );
+        let blocks = |value: DB721Type| {
+            db721.matching_blocks(&[Predicate::new(String::from("id"), CompareOp::Eq, value)])
+        };
+        assert!(blocks(uuid(42)).contains(&0));
+        assert!(blocks(uuid(142)).contains(&1));
+        // 第一个字节大于所有值时跳过所有block
+        assert_eq!(
+            blocks(DB721Type::Binary(Binary::new(vec![0xff; 16]))),
+            Vec::<i32>::new()
+        );
+
+        let mut cursor = column_cursor(&db721, "id");
+        cursor.skip(120);
+        for idx in 120..250 {
+            let (batch, value_idx) = cursor.advance().unwrap();
+            assert_eq!(batch.binary_bytes(value_idx), uuid(idx).to_le_bytes());
+        }
+        let (batch, value_idx) = cursor.advance().unwrap();
+        assert_eq!(
+            batch.get(value_idx).to_string(),
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"
+        );
+    }
+    #[test]
+    fn test_writer_bloom_filter() {
+        // 乱序的偶数，每个block的min/max几乎覆盖整个取值范围
+        let id = |idx: i64| idx * 7919 % 1000 * 2;
+        let rows = (0..1000).map(|idx| {
+            [
+                Some(DB721Type::BigInt(id(idx))),
+                (idx % 10 != 0).then(|| DB721Type::Str(format!("customer-{}", id(idx)))),
+                Some(DB721Type::Float(idx as f32)),
+            ]
+        });
+        let file = write_test_file(
+            "writer_bloom_filter",
+            100,
+            &[("id", "bigint"), ("customer", "str"), ("weight", "float")],
+            rows,
+            |writer| {
+                assert!(writer.set_bloom_columns(&[String::from("weight")]).is_err());
+                assert!(writer
+                    .set_bloom_columns(&[String::from("missing")])
+                    .is_err());
+                writer
+                    .set_bloom_columns(&[String::from("id"), String::from("customer")])
+                    .unwrap();
+            },
+        );
+
+        let db721 = file.open();
+        let block_meta = db721.meta.column_meta["id"].block_meta(0).unwrap();
+        assert!(block_meta.bloom().is_some());
+        assert!(block_meta.min() < &DB721Type::BigInt(10));
+        assert!(block_meta.max() > &DB721Type::BigInt(1900));
+        assert!(db721.meta.column_meta["weight"]
+            .block_meta(0)
+            .unwrap()
+            .bloom()
+            .is_none());
+        let eq = |column_name: &str, value: DB721Type| {
+            db721.matching_blocks(&[Predicate::new(
+                column_name.to_string(),
+                CompareOp::Eq,
+                value,
+            )])
+        };
+        for idx in [0, 123, 999] {
+            let block_idx = idx as i32 / 100;
+            assert!(eq("id", DB721Type::BigInt(id(idx))).contains(&block_idx));
+            // int常量先转换为bigint再计算哈希
+            assert!(eq("id", DB721Type::Integer(id(idx) as i32)).contains(&block_idx));
+        }
+        let customer = DB721Type::Str(format!("customer-{}", id(123)));
+        assert!(eq("customer", customer).contains(&1));
+        // 不存在的奇数在min/max范围内，但几乎所有block都能被bloom filter排除
+        let false_positives: usize = (0..1000)
+            .map(|idx| eq("id", DB721Type::BigInt(idx * 2 + 1)).len())
+            .sum();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        let pred = Predicate::new_in(
+            String::from("id"),
+            vec![DB721Type::BigInt(1), DB721Type::BigInt(id(555))],
+        )
+        .unwrap();
+        assert!(db721.matching_blocks(&[pred]).contains(&5));
+    }
+}
@@ -0,0 +1,273 @@
+/// 将where子句转换为db721的谓词，用于跳过block及文件
+use crate::db721::predicate::{CompareOp, Predicate};
+use crate::db721::binary::Binary;
+use crate::db721::{datetime, DB721Type};
+use pgrx::pg_sys::{
+    get_collation_isdeterministic, get_op_opfamily_strategy, get_opclass_family, lc_collate_is_c,
+    op_input_types, Const, FormData_pg_attribute, GetDefaultOpClass, InvalidOid, List, ListCell,
+    Node, NodeTag_T_Const, NodeTag_T_NullTest, NodeTag_T_OpExpr, NodeTag_T_RelabelType,
+    NodeTag_T_ScalarArrayOpExpr, NodeTag_T_Var, NullTest, NullTestType_IS_NULL, Oid, OpExpr,
+    RelabelType, ScalarArrayOpExpr, TupleDesc, Var, BOOLOID, BTREE_AM_OID, BYTEAARRAYOID, BYTEAOID,
+    DATEOID, FLOAT4ARRAYOID, FLOAT4OID, FLOAT8ARRAYOID, FLOAT8OID, INT2ARRAYOID, INT2OID,
+    INT4ARRAYOID, INT4OID, INT8ARRAYOID, INT8OID, NUMERICARRAYOID, NUMERICOID, TEXTARRAYOID,
+    TEXTOID, TIMESTAMPTZOID, UUIDARRAYOID, UUIDOID, VARCHARARRAYOID, VARCHAROID,
+};
+use pgrx::{AnyNumeric, FromDatum, Uuid};
+use std::ffi::CStr;
+
+/// 去掉类型转换（如varchar到text）外壳
+unsafe fn strip_relabel(node: *mut Node) -> *mut Node {
+    let mut node = node;
+    while !node.is_null() && (*node).type_ == NodeTag_T_RelabelType {
+        node = (*(node as *mut RelabelType)).arg as *mut Node;
+    }
+    node
+}
+/// 将常量转换为DB721Type，暂不支持的类型返回None
+unsafe fn db721_const_value(constant: *mut Const) -> Option<DB721Type> {
+    if (*constant).constisnull {
+        return None;
+    }
+    let datum = (*constant).constvalue;
+    let const_type = (*constant).consttype;
+    if const_type == INT4OID {
+        i32::from_datum(datum, false).map(DB721Type::Integer)
+    } else if const_type == INT2OID {
+        i16::from_datum(datum, false).map(|val| DB721Type::Integer(val as i32))
+    } else if const_type == INT8OID {
+        i64::from_datum(datum, false).map(DB721Type::BigInt)
+    } else if const_type == FLOAT4OID {
+        f32::from_datum(datum, false).map(DB721Type::Float)
+    } else if const_type == FLOAT8OID {
+        f64::from_datum(datum, false).map(DB721Type::Double)
+    } else if const_type == BOOLOID {
+        bool::from_datum(datum, false).map(DB721Type::Bool)
+    } else if const_type == TEXTOID || const_type == VARCHAROID {
+        // bpchar比较时忽略尾部空格，与按字节比较的min/max不一致，不下推
+        String::from_datum(datum, false).map(DB721Type::Str)
+    } else if const_type == DATEOID {
+        // 分区列（如 date=2023-01-01）的取值按日期解析后比较，infinity不下推
+        i32::from_datum(datum, false)
+            .and_then(datetime::date_from_pg)
+            .map(DB721Type::Date)
+    } else if const_type == TIMESTAMPTZOID {
+        i64::from_datum(datum, false)
+            .and_then(datetime::timestamp_from_pg)
+            .map(DB721Type::Timestamp)
+    } else if const_type == NUMERICOID {
+        // NaN和infinity无法解析，不下推
+        AnyNumeric::from_datum(datum, false)
+            .and_then(|val| val.to_string().parse().ok())
+            .map(DB721Type::Decimal)
+    } else if const_type == UUIDOID {
+        Uuid::from_datum(datum, false).map(|val| DB721Type::Binary(Binary::new(val.as_bytes().to_vec())))
+    } else if const_type == BYTEAOID {
+        Vec::<u8>::from_datum(datum, false).map(|val| DB721Type::Binary(Binary::new(val)))
+    } else {
+        None
+    }
+}
+/// 将数组常量中的非NULL元素转换为DB721Type，暂不支持的元素类型返回None
+unsafe fn db721_const_array_values(constant: *mut Const) -> Option<Vec<DB721Type>> {
+    if (*constant).constisnull {
+        return None;
+    }
+    let datum = (*constant).constvalue;
+    let const_type = (*constant).consttype;
+    let values = if const_type == INT4ARRAYOID {
+        Vec::<Option<i32>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(DB721Type::Integer)
+            .collect()
+    } else if const_type == INT2ARRAYOID {
+        Vec::<Option<i16>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(|val| DB721Type::Integer(val as i32))
+            .collect()
+    } else if const_type == INT8ARRAYOID {
+        Vec::<Option<i64>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(DB721Type::BigInt)
+            .collect()
+    } else if const_type == FLOAT4ARRAYOID {
+        Vec::<Option<f32>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(DB721Type::Float)
+            .collect()
+    } else if const_type == FLOAT8ARRAYOID {
+        Vec::<Option<f64>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(DB721Type::Double)
+            .collect()
+    } else if const_type == NUMERICARRAYOID {
+        // decimal列中没有NaN和infinity，去掉这些元素不影响结果
+        Vec::<Option<AnyNumeric>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .filter_map(|val| val.to_string().parse().ok())
+            .map(DB721Type::Decimal)
+            .collect()
+    } else if const_type == UUIDARRAYOID {
+        Vec::<Option<Uuid>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(|val| DB721Type::Binary(Binary::new(val.as_bytes().to_vec())))
+            .collect()
+    } else if const_type == BYTEAARRAYOID {
+        Vec::<Option<Vec<u8>>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(|val| DB721Type::Binary(Binary::new(val)))
+            .collect()
+    } else if const_type == TEXTARRAYOID || const_type == VARCHARARRAYOID {
+        Vec::<Option<String>>::from_datum(datum, false)?
+            .into_iter()
+            .flatten()
+            .map(DB721Type::Str)
+            .collect()
+    } else {
+        return None;
+    };
+    Some(values)
+}
+/// 获取列名
+pub unsafe fn db721_attribute_name(tuple_desc: TupleDesc, attno: i16) -> String {
+    let attr_form = (((*tuple_desc).attrs.as_mut_ptr()) as *mut FormData_pg_attribute)
+        .add(attno as usize - 1);
+    CStr::from_ptr((*attr_form).attname.data.as_ptr())
+        .to_str()
+        .expect("column name should be valid UTF-8")
+        .to_string()
+}
+/// 按操作符在btree操作符族中的策略号确定比较方式。只认左操作数类型的默认btree操作符族中的操作符，
+/// 不按名字识别，用户自定义的同名操作符不会下推
+unsafe fn db721_btree_compare_op(opno: Oid) -> Option<CompareOp> {
+    let mut left_type = InvalidOid;
+    let mut right_type = InvalidOid;
+    op_input_types(opno, &mut left_type, &mut right_type);
+    let opclass = GetDefaultOpClass(left_type, BTREE_AM_OID);
+    if opclass == InvalidOid {
+        return None;
+    }
+    let strategy = get_op_opfamily_strategy(opno, get_opclass_family(opclass));
+    CompareOp::from_btree_strategy(u16::try_from(strategy).ok()?)
+}
+/// 字符串的min/max及字典按字节比较：非确定性排序规则（如大小写不敏感）下相等不代表字节相同，
+/// 都不下推；范围条件只有C排序规则下的大小关系与之一致
+unsafe fn db721_str_op_pushable(op: CompareOp, collation: Oid) -> bool {
+    if collation == InvalidOid || !get_collation_isdeterministic(collation) {
+        return false;
+    }
+    op == CompareOp::Eq || lc_collate_is_c(collation)
+}
+/// 从 `Var op Const` 或 `Const op Var` 形式的子句中提取谓词
+unsafe fn db721_op_expr_predicate(op_expr: *mut OpExpr, tuple_desc: TupleDesc) -> Option<Predicate> {
+    let args = (*op_expr).args;
+    if args.is_null() || (*args).length != 2 {
+        return None;
+    }
+    let left = strip_relabel((*(*args).elements.add(0)).ptr_value as *mut Node);
+    let right = strip_relabel((*(*args).elements.add(1)).ptr_value as *mut Node);
+    let op = db721_btree_compare_op((*op_expr).opno)?;
+    let (var, constant, op) = if (*left).type_ == NodeTag_T_Var && (*right).type_ == NodeTag_T_Const {
+        (left as *mut Var, right as *mut Const, op)
+    } else if (*left).type_ == NodeTag_T_Const && (*right).type_ == NodeTag_T_Var {
+        (right as *mut Var, left as *mut Const, op.commute())
+    } else {
+        return None;
+    };
+    if (*var).varattno <= 0 {
+        return None;
+    }
+    let value = db721_const_value(constant)?;
+    if matches!(value, DB721Type::Str(_)) && !db721_str_op_pushable(op, (*op_expr).inputcollid) {
+        return None;
+    }
+    Some(Predicate::new(
+        db721_attribute_name(tuple_desc, (*var).varattno),
+        op,
+        value,
+    ))
+}
+/// 从 `Var = ANY(Const数组)`（即 `col IN (...)`）形式的子句中提取谓词
+unsafe fn db721_scalar_array_op_predicate(
+    array_op_expr: *mut ScalarArrayOpExpr,
+    tuple_desc: TupleDesc,
+) -> Option<Predicate> {
+    let args = (*array_op_expr).args;
+    if !(*array_op_expr).useOr || args.is_null() || (*args).length != 2 {
+        return None;
+    }
+    let left = strip_relabel((*(*args).elements.add(0)).ptr_value as *mut Node);
+    let right = strip_relabel((*(*args).elements.add(1)).ptr_value as *mut Node);
+    if db721_btree_compare_op((*array_op_expr).opno) != Some(CompareOp::Eq) {
+        return None;
+    }
+    if (*left).type_ != NodeTag_T_Var || (*right).type_ != NodeTag_T_Const {
+        return None;
+    }
+    let var = left as *mut Var;
+    if (*var).varattno <= 0 {
+        return None;
+    }
+    let values = db721_const_array_values(right as *mut Const)?;
+    if matches!(values.first(), Some(DB721Type::Str(_)))
+        && !db721_str_op_pushable(CompareOp::Eq, (*array_op_expr).inputcollid)
+    {
+        return None;
+    }
+    Predicate::new_in(db721_attribute_name(tuple_desc, (*var).varattno), values)
+}
+/// 从 `Var IS NULL` / `Var IS NOT NULL` 形式的子句中提取谓词
+unsafe fn db721_null_test_predicate(null_test: *mut NullTest, tuple_desc: TupleDesc) -> Option<Predicate> {
+    if (*null_test).argisrow {
+        return None;
+    }
+    let arg = strip_relabel((*null_test).arg as *mut Node);
+    if arg.is_null() || (*arg).type_ != NodeTag_T_Var {
+        return None;
+    }
+    let var = arg as *mut Var;
+    if (*var).varattno <= 0 {
+        return None;
+    }
+    Some(Predicate::new_null_test(
+        db721_attribute_name(tuple_desc, (*var).varattno),
+        (*null_test).nulltesttype == NullTestType_IS_NULL,
+    ))
+}
+/// 从子句列表（已去掉RestrictInfo外壳）中提取所有能识别的谓词，
+/// 这些子句之间是AND关系，无法识别的子句直接忽略
+pub fn db721_extract_predicates(clause_list: *mut List, tuple_desc: TupleDesc) -> Vec<Predicate> {
+    let mut predicates = Vec::new();
+    unsafe {
+        if clause_list.is_null() {
+            return predicates;
+        }
+        for i in 0..(*clause_list).length {
+            let list_cell = (*clause_list).elements.add(i as usize) as *mut ListCell;
+            let clause = (*list_cell).ptr_value as *mut Node;
+            if clause.is_null() {
+                continue;
+            }
+            let predicate = if (*clause).type_ == NodeTag_T_OpExpr {
+                db721_op_expr_predicate(clause as *mut OpExpr, tuple_desc)
+            } else if (*clause).type_ == NodeTag_T_ScalarArrayOpExpr {
+                db721_scalar_array_op_predicate(clause as *mut ScalarArrayOpExpr, tuple_desc)
+            } else if (*clause).type_ == NodeTag_T_NullTest {
+                db721_null_test_predicate(clause as *mut NullTest, tuple_desc)
+            } else {
+                None
+            };
+            if let Some(predicate) = predicate {
+                predicates.push(predicate);
+            }
+        }
+    }
+    predicates
+}
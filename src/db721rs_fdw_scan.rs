@@ -1,10 +1,19 @@
 /// this mod aims to impl some scan callbacks for db721 file
-use crate::db721::{ColumnIterator, ColumnIteratorBuilder, DB721, DB721Type};
+use crate::db721::dataset::{DB721Dataset, DB721Source};
+use crate::db721::predicate::Predicate;
+use crate::db721::{
+    binary, datetime, decimal, BatchValues, BlockBatch, ColumnCursor, ColumnIteratorBuilder, RowTracker, DB721,
+};
+use crate::db721rs_fdw_qual::db721_extract_predicates;
+use crate::db721rs_functions::{db721_has_role, READ_SERVER_FILES};
 use anyhow::Context;
 use libc::{c_uchar, memcpy, memset, size_t, strncmp};
-use pgrx::pg_sys::{cluster_name, defGetString, extract_actual_clauses, get_attname, lappend, list_concat, list_copy, list_make1_impl, list_union, makeVar, make_foreignscan, palloc0, pull_var_clause, relation_close, relation_open, scalararraysel, AccessShareLock, AttrNumber, BeginForeignScan_function, Cardinality, DefElem, ForEachState, ForeignScan, ForeignScanState, FormData_pg_attribute, GetForeignTable, List, ListCell, Node, NodeTag_T_List, Oid, PLpgSQL_stmt_foreach_a, PlannerInfo, RelOptInfo, Relation, RelationGetReplicaIndex, RestrictInfo, Size, TupleDesc, TupleDescGetAttInMetadata, Var, EXEC_FLAG_EXPLAIN_ONLY, LOCKMODE, NAMEDATALEN, PVC_RECURSE_AGGREGATES, PVC_RECURSE_PLACEHOLDERS, TupleTableSlot, Datum, Hash, ExecStoreVirtualTuple, DatumTupleFields, varlena, VarChar, VARHDRSZ, VariableStatData};
+use pgrx::pg_sys::{fmgr_info, getTypeInputInfo, FmgrInfo, InputFunctionCall, OidInputFunctionCall, AllocSetContextCreateInternal, MemoryContext, MemoryContextAlloc, MemoryContextCallback, MemoryContextDelete, MemoryContextRegisterResetCallback, ALLOCSET_DEFAULT_INITSIZE, ALLOCSET_DEFAULT_MAXSIZE, ALLOCSET_DEFAULT_MINSIZE, cluster_name, cstring_to_text_with_len, defGetString, extract_actual_clauses, get_attname, lappend, list_concat, list_copy, list_make1_impl, list_union, makeVar, make_foreignscan, palloc0, pull_var_clause, relation_close, relation_open, scalararraysel, AccessShareLock, AttrNumber, BeginForeignScan_function, Cardinality, DefElem, ForEachState, ForeignScan, ForeignScanState, FormData_pg_attribute, GetForeignColumnOptions, GetForeignTable, List, ListCell, Node, NodeTag_T_List, Oid, PLpgSQL_stmt_foreach_a, PlannerInfo, RelOptInfo, Relation, RelationGetReplicaIndex, RestrictInfo, Size, TupleDesc, TupleDescGetAttInMetadata, Var, EXEC_FLAG_EXPLAIN_ONLY, LOCKMODE, NAMEDATALEN, PVC_RECURSE_AGGREGATES, PVC_RECURSE_PLACEHOLDERS, TupleTableSlot, Datum, Hash, ExecStoreVirtualTuple, DatumTupleFields, varlena, VarChar, VARHDRSZ, VariableStatData};
 use pgrx::prelude::*;
-use pgrx::{ereport, pg_guard, void_mut_ptr, PgList, PgLogLevel, NULL};
+use pgrx::{
+    check_for_interrupts, ereport, extension_sql, pg_getarg, pg_getarg_datum, pg_guard, void_mut_ptr, PgList,
+    PgLogLevel, NULL,
+};
 use std::collections::HashMap;
 use std::ffi::{c_char, c_int, c_void, CStr, CString};
 use std::io::Write;
@@ -100,35 +109,100 @@ macro_rules! warning_log {
         );
     }
 }
+/// 通过列选项 virtual_column 启用的伪列，不对应文件中的任何数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DB721VirtualColumn {
+    /// 当前行所在文件的路径
+    SourceFile,
+    /// 当前行所在block的下标
+    BlockIndex,
+    /// 当前行在文件中的行号（从0开始）
+    RowNumber,
+}
+impl DB721VirtualColumn {
+    pub fn from_option_value(value: &str) -> Option<Self> {
+        match value {
+            "source_file" => Some(DB721VirtualColumn::SourceFile),
+            "block_index" => Some(DB721VirtualColumn::BlockIndex),
+            "row_number" => Some(DB721VirtualColumn::RowNumber),
+            _ => None,
+        }
+    }
+    /// source_file需要声明为text/varchar，block_index和row_number需要声明为int2/int4/int8
+    pub fn type_matches(&self, type_oid: Oid) -> bool {
+        match self {
+            DB721VirtualColumn::SourceFile => {
+                type_oid == pg_sys::TEXTOID || type_oid == pg_sys::VARCHAROID
+            }
+            DB721VirtualColumn::BlockIndex | DB721VirtualColumn::RowNumber => matches!(
+                type_oid,
+                pg_sys::INT2OID | pg_sys::INT4OID | pg_sys::INT8OID
+            ),
+        }
+    }
+}
 pub struct DB721ScanState {
-    db721: DB721,
+    /// 经过谓词过滤后需要扫描的文件
+    db721_files: Vec<DB721>,
+    next_file_idx: usize,
+    predicates: Vec<Predicate>,
     column_list: *mut List,
     tuple_desc: TupleDesc,
     where_clause_list: *mut List,
-    column_iterators: HashMap<String, ColumnIterator>,
+    column_cursors: HashMap<String, ColumnCursor>,
     column_index_map_name: HashMap<i16, String>,
+    /// 当前文件中分区列（来自目录名）的取值，分配在扫描内存上下文中
+    partition_datums: HashMap<i16, Datum>,
+    /// 伪列及其类型
+    virtual_columns: HashMap<i16, (DB721VirtualColumn, Oid)>,
+    /// decimal列构造numeric时复用的输入函数
+    numeric_input: DB721NumericInput,
+    /// 跟踪当前文件中的扫描位置，没有需要从文件读取的列时（如count(*)
+    /// 或只查询分区列、伪列）也依靠它判断文件是否读完
+    row_tracker: Option<RowTracker>,
+    /// 扫描状态所在的内存上下文，EndForeignScan时删除
+    scan_memory_context: MemoryContext,
 }
 
 impl DB721ScanState {
-    /// 这里返回 *mut Option<DB721ScanState> 的原因是为了能够
-    /// 在foreign_scan_end函数中，对rust自动申请的堆内存进行释
-    /// 放（使用std::mem::replace）
+    /// 扫描状态本身分配在parent下新建的扫描内存上下文中，返回
+    /// *mut Option<DB721ScanState>。该上下文被删除或重置时（包括
+    /// 扫描中途出错），注册的回调会将其替换为None，从而释放rust
+    /// 自动申请的堆内存（打开的文件、缓存的block等）
     #[pg_guard]
     pub fn new(
-        db_721: DB721,
+        mut dataset: DB721Dataset,
+        predicates: Vec<Predicate>,
+        relation_id: Oid,
         tuple_desc: TupleDesc,
         column_list: *mut List,
         where_clause_list: *mut List,
+        parent_memory_context: MemoryContext,
     ) -> *mut Option<DB721ScanState> {
         unsafe {
             // warning_log!("in db_721_scan_state new func");
+            let scan_memory_context = AllocSetContextCreateInternal(
+                parent_memory_context,
+                b"db721_fdw scan state\0".as_ptr() as *const c_char,
+                ALLOCSET_DEFAULT_MINSIZE as Size,
+                ALLOCSET_DEFAULT_INITSIZE as Size,
+                ALLOCSET_DEFAULT_MAXSIZE as Size,
+            );
+            dataset.prune(&predicates);
             let mut state = DB721ScanState{
-                db721: db_721.clone(),
+                db721_files: dataset.files,
+                next_file_idx: 0,
+                predicates,
                 column_list,
                 tuple_desc,
                 where_clause_list,
-                column_iterators: HashMap::new(),
+                column_cursors: HashMap::new(),
                 column_index_map_name: HashMap::new(),
+                partition_datums: HashMap::new(),
+                virtual_columns: HashMap::new(),
+                numeric_input: DB721NumericInput::default(),
+                row_tracker: None,
+                scan_memory_context,
             };
             let column_count = (*tuple_desc).natts;
             for index in 0..(*column_list).length {
@@ -139,29 +213,147 @@ impl DB721ScanState {
                 let column_name_raw = (*attr_form).attname.data.as_mut_ptr();
                 let column_name_cstr = CStr::from_ptr(column_name_raw);
                 let column_name = column_name_cstr.to_str().unwrap();
-                let column_iterator_builder = ColumnIteratorBuilder::new(
-                    db_721.meta.column_meta.get(column_name).unwrap().clone(),
-                    column_name.to_string(),
-                    db_721.path.clone(),
+                state.column_index_map_name.insert((*column).varattno -1, column_name.to_string());
+                if let Some(virtual_column) =
+                    db721_find_column_option_value(relation_id, (*column).varattno, "virtual_column")
+                {
+                    let virtual_column = DB721VirtualColumn::from_option_value(&virtual_column)
+                        .unwrap_or_else(|| {
+                            error!(
+                                "invalid virtual_column {} for column {}, expect source_file, block_index or row_number",
+                                virtual_column, column_name
+                            )
+                        });
+                    if !virtual_column.type_matches((*attr_form).atttypid) {
+                        let expected = match virtual_column {
+                            DB721VirtualColumn::SourceFile => "text or varchar",
+                            _ => "smallint, integer or bigint",
+                        };
+                        error!("virtual column {} should be declared as {}", column_name, expected);
+                    }
+                    state.virtual_columns.insert(
+                        (*column).varattno - 1,
+                        (virtual_column, (*attr_form).atttypid),
+                    );
+                }
+            }
+            state.open_next_file();
+            let p_state = MemoryContextAlloc(
+                scan_memory_context,
+                size_of::<Option<DB721ScanState>>() as Size,
+            ) as *mut Option<DB721ScanState>;
+            ptr::write(p_state, Some(state));
+            let reset_callback = MemoryContextAlloc(
+                scan_memory_context,
+                size_of::<MemoryContextCallback>() as Size,
+            ) as *mut MemoryContextCallback;
+            (*reset_callback).func = Some(db721_scan_state_reset_callback);
+            (*reset_callback).arg = p_state as *mut c_void;
+            (*reset_callback).next = ptr::null_mut();
+            MemoryContextRegisterResetCallback(scan_memory_context, reset_callback);
+            p_state
+        }
+    }
+    /// 为下一个文件创建各列的游标，只读取谓词可能满足的block，
+    /// 没有文件可读时返回false
+    pub fn open_next_file(&mut self) -> bool {
+        self.column_cursors.clear();
+        self.partition_datums.clear();
+        while self.next_file_idx < self.db721_files.len() {
+            let db721 = &self.db721_files[self.next_file_idx];
+            self.next_file_idx += 1;
+            let block_indices = db721.matching_blocks(&self.predicates);
+            if block_indices.is_empty() {
+                continue;
+            }
+            self.row_tracker = Some(RowTracker::new(db721, &block_indices));
+            for (column_index, column_name) in self.column_index_map_name.iter() {
+                if self.virtual_columns.contains_key(column_index) {
+                    continue;
+                }
+                if !db721.meta.column_meta.contains_key(column_name) {
+                    let Some(partition_value) = db721.partition_values.get(column_name) else {
+                        error!(
+                            "column {} is neither in {} nor a partition column",
+                            column_name,
+                            db721.path.display()
+                        );
+                    };
+                    let datum = unsafe {
+                        db721_partition_datum(
+                            self.tuple_desc,
+                            *column_index,
+                            partition_value,
+                            self.scan_memory_context,
+                        )
+                    };
+                    self.partition_datums.insert(*column_index, datum);
+                    continue;
+                }
+                let mut column_iterator_builder = ColumnIteratorBuilder::new(
+                    db721.meta.column_meta.get(column_name).unwrap().clone(),
+                    column_name.clone(),
+                    db721.path.clone(),
                 );
+                column_iterator_builder
+                    .set_interrupt_check(db721_check_for_interrupts)
+                    .set_block_indices(block_indices.clone());
                 let column_iterator = column_iterator_builder.build().unwrap();
-                state.column_iterators.insert(column_name.to_string(), column_iterator);
-                state.column_index_map_name.insert((*column).varattno -1, column_name.to_string());
+                self.column_cursors
+                    .insert(column_name.clone(), ColumnCursor::new(column_iterator));
             }
-            let b_state = Box::new(Some(state));
-            Box::leak(b_state)
+            return true;
         }
+        false
+    }
+    /// 当前正在扫描的文件
+    pub fn current_file(&self) -> &DB721 {
+        &self.db721_files[self.next_file_idx - 1]
     }
     pub fn is_end(&self) -> bool{
-        for column_it in self.column_iterators.values(){
-            if !column_it.is_end() {
+        if self.column_cursors.is_empty() {
+            return self.row_tracker.as_ref().is_none_or(|row_tracker| row_tracker.is_end());
+        }
+        for column_cursor in self.column_cursors.values(){
+            if !column_cursor.is_end() {
                 return false
             }
         }
         true
     }
 }
-/// 行数预测
+/// 使用列类型的输入函数将分区取值（字符串）转换为Datum，
+/// 结果分配在memory_context中，在整个文件的扫描过程中复用
+unsafe fn db721_partition_datum(
+    tuple_desc: TupleDesc,
+    column_index: i16,
+    partition_value: &str,
+    memory_context: MemoryContext,
+) -> Datum {
+    let attr_form = (((*tuple_desc).attrs.as_mut_ptr()) as *mut FormData_pg_attribute)
+        .add(column_index as usize);
+    let mut type_input: Oid = pg_sys::InvalidOid;
+    let mut type_io_param: Oid = pg_sys::InvalidOid;
+    getTypeInputInfo((*attr_form).atttypid, &mut type_input, &mut type_io_param);
+    let value_cstr = CString::new(partition_value).expect("CString::new failed");
+    let old_memory_context = pg_sys::CurrentMemoryContext;
+    pg_sys::CurrentMemoryContext = memory_context;
+    let datum = OidInputFunctionCall(
+        type_input,
+        value_cstr.as_ptr() as *mut c_char,
+        type_io_param,
+        (*attr_form).atttypmod,
+    );
+    pg_sys::CurrentMemoryContext = old_memory_context;
+    datum
+}
+/// 规划阶段保存在RelOptInfo.fdw_private中的信息，用于代价估计
+#[repr(C)]
+struct DB721RelInfo {
+    file_count: f64,
+    row_count: f64,
+}
+/// 行数预测，对所有未被谓词排除的文件求和
 #[pg_guard]
 pub extern "C" fn db721_get_foreign_rel_size(
     root: *mut pg_sys::PlannerInfo,
@@ -169,18 +361,23 @@ pub extern "C" fn db721_get_foreign_rel_size(
     foreign_table_id: pg_sys::Oid,
 ) {
     unsafe {
-        // 首先获取表对应的文件名
-        let file_name = db721_get_option_value(foreign_table_id, literal_str_to_cstr!("filename"));
-        let cstr_file_name = CStr::from_ptr(file_name);
-        // 进行文件元信息的读取
-        let db721_table = DB721::open(PathBuf::from(
-            cstr_file_name
-                .to_str()
-                .expect("file_name should be valid UTF-8"),
-        ))
-        .unwrap();
+        // 首先获取表对应的文件，并进行文件元信息的读取
+        let relation: Relation = relation_open(foreign_table_id, AccessShareLock as c_int);
+        let restrict_clause_list = extract_actual_clauses((*base_rel).baserestrictinfo, false);
+        let predicates = db721_extract_predicates(restrict_clause_list, (*relation).rd_att);
+        relation_close(relation, AccessShareLock as LOCKMODE);
+        let source = db721_table_source(foreign_table_id);
+        let mut dataset = DB721Dataset::open_with_predicates(&source, &predicates)
+            .with_context(|| "failed to open db721 files in db721_get_foreign_rel_size")
+            .unwrap();
+        dataset.prune(&predicates);
         // 获取行数量，并赋值给pg中的对象
-        (*base_rel).rows = db721_table.row_count() as Cardinality;
+        let row_count = dataset.row_count() as Cardinality;
+        (*base_rel).rows = row_count;
+        let rel_info = palloc0(size_of::<DB721RelInfo>()) as *mut DB721RelInfo;
+        (*rel_info).file_count = dataset.files.len() as f64;
+        (*rel_info).row_count = row_count;
+        (*base_rel).fdw_private = rel_info as *mut c_void;
     }
 }
 /// 准备开始扫表，主要工作是创建扫描状态。
@@ -193,30 +390,29 @@ pub extern "C" fn db721_begin_foreign_scan(node: *mut ForeignScanState, e_flags:
         if (e_flags & EXEC_FLAG_EXPLAIN_ONLY as c_int) != 0 {
             return;
         }
-        // 获取文件名（C字符串）
-        let mut filename_raw = db721_get_option_value(
-            relation_id,
-            CString::new("filename")
-                .expect("CString::new failed")
-                .into_raw(),
-        );
-        let file_name = CStr::from_ptr(filename_raw)
-            .to_str()
-            .expect("convert filename to UTF-8 failed");
         let foreign_scan = (*node).ss.ps.plan as *mut ForeignScan;
         let foreign_private_list = (*foreign_scan).fdw_private as *mut List;
         let where_clause_list = (*foreign_scan).scan.plan.qual as *mut List;
         let pl_first_cell = l_nth_cell!(foreign_private_list, 0);
         let column_list = l_first!(pl_first_cell) as *mut List;
-        let db_721 = DB721::open(PathBuf::from(file_name))
-            .with_context(|| "failed to create db_721 in db721_begin_foreign_scan")
-            .unwrap();
-        let db721_scan_state =
-            DB721ScanState::new(db_721, tuple_desc, column_list, where_clause_list);
+        let predicates = db721_extract_predicates(where_clause_list, tuple_desc);
+        let dataset =
+            DB721Dataset::open_with_predicates(&db721_table_source(relation_id), &predicates)
+                .with_context(|| "failed to create db_721 in db721_begin_foreign_scan")
+                .unwrap();
+        let db721_scan_state = DB721ScanState::new(
+            dataset,
+            predicates,
+            relation_id,
+            tuple_desc,
+            column_list,
+            where_clause_list,
+            (*(*node).ss.ps.state).es_query_cxt,
+        );
         (*node).fdw_state = db721_scan_state as *mut c_void;
     }
 }
-// 主要用于成本估计，后续编写成本估计函数，现在为了简单起见，暂时不估计。
+/// 成本估计：每个文件需要一次随机读取footer，每一行按cpu_tuple_cost计算
 #[pg_guard]
 pub extern "C" fn db721_get_foreign_paths(
     root: *mut pg_sys::PlannerInfo,
@@ -224,13 +420,16 @@ pub extern "C" fn db721_get_foreign_paths(
     foreign_table_id: pg_sys::Oid,
 ) {
     unsafe {
+        let rel_info = (*base_rel).fdw_private as *mut DB721RelInfo;
+        let startup_cost = (*rel_info).file_count * pg_sys::random_page_cost;
+        let total_cost = startup_cost + (*rel_info).row_count * pg_sys::cpu_tuple_cost;
         let path = pg_sys::create_foreignscan_path(
             root,
             base_rel,
             ptr::null_mut(), // default pathtarget
             (*base_rel).rows,
-            0.0,
-            0.0,
+            startup_cost,
+            total_cost,
             ptr::null_mut(), // no pathkeys
             ptr::null_mut(), // no outer rel either
             ptr::null_mut(), // no extra plan
@@ -297,30 +496,188 @@ pub extern "C" fn db721_iterate_foreign_scan(
             column_count as size_t * size_of::<bool>(),
         );
         (*((*tuple_table_slot).tts_ops)).clear.unwrap()(tuple_table_slot);
+        // 行数据分配在per-tuple上下文中，执行器每取一行前都会重置它
+        let per_tuple_memory_context = (*(*node).ss.ps.ps_ExprContext).ecxt_per_tuple_memory;
+        let old_memory_context = pg_sys::CurrentMemoryContext;
+        pg_sys::CurrentMemoryContext = per_tuple_memory_context;
         // 读取下一行
         let next_row_found = db721_read_next_row(
             db721_scan_state,
             column_values,
             column_nulls,
         );
+        pg_sys::CurrentMemoryContext = old_memory_context;
         if next_row_found{
             ExecStoreVirtualTuple(tuple_table_slot);
         }
         tuple_table_slot
     }
 }
-/// 利用replace手动触发drop机制，释放rust自动申请的堆内存。
+/// 删除扫描内存上下文，由其重置回调释放rust自动申请的堆内存。
 /// 防止内存泄漏
 #[pg_guard]
 pub extern "C" fn db721_end_foreign_scan(node: *mut pg_sys::ForeignScanState) {
     unsafe {
-        /// 利用replace手动触发drop，释放rust自动申请的堆内存。
-        /// 防止内存泄漏
-        std::mem::replace(
-            &mut *((*node).fdw_state as *mut Option<DB721ScanState>),
-            Option::<DB721ScanState>::None,
+        // EXPLAIN时没有创建扫描状态
+        if (*node).fdw_state.is_null() {
+            return;
+        }
+        let scan_memory_context =
+            def_option_ptr!(*((*node).fdw_state as *mut Option<DB721ScanState>)).scan_memory_context;
+        MemoryContextDelete(scan_memory_context);
+        (*node).fdw_state = ptr::null_mut();
+    }
+}
+/// 扫描内存上下文的重置回调，利用replace手动触发drop，释放rust
+/// 自动申请的堆内存。扫描出错中止时同样会被调用
+#[pg_guard]
+pub unsafe extern "C" fn db721_scan_state_reset_callback(arg: *mut c_void) {
+    let _ = std::mem::replace(
+        &mut *(arg as *mut Option<DB721ScanState>),
+        Option::<DB721ScanState>::None,
+    );
+}
+/// from foreign_table's option, get the value of option_name,
+/// return None (without warning) if the option is absent
+pub fn db721_find_option_value(foreign_table_id: Oid, option_name: &str) -> Option<String> {
+    unsafe { db721_find_def_elem_value((*GetForeignTable(foreign_table_id)).options, option_name) }
+}
+/// 获取外部表某一列的选项值
+pub fn db721_find_column_option_value(
+    foreign_table_id: Oid,
+    attnum: AttrNumber,
+    option_name: &str,
+) -> Option<String> {
+    unsafe {
+        db721_find_def_elem_value(GetForeignColumnOptions(foreign_table_id, attnum), option_name)
+    }
+}
+/// 在DefElem列表中查找名为option_name的选项
+unsafe fn db721_find_def_elem_value(option_list: *mut List, option_name: &str) -> Option<String> {
+    if option_list.is_null() {
+        return None;
+    }
+    for i in 0..(*option_list).length {
+        let list_cell = l_nth_cell!(option_list, i) as *mut ListCell;
+        let option_def = l_first!(list_cell) as *mut DefElem;
+        if CStr::from_ptr((*option_def).defname).to_bytes() == option_name.as_bytes() {
+            let option_value = CStr::from_ptr(defGetString(option_def))
+                .to_str()
+                .expect("option value should be valid UTF-8");
+            return Some(option_value.to_string());
+        }
+    }
+    None
+}
+/// 外部表的数据来源：filename（单个文件）、filenames（glob模式）
+/// 或directory（目录下所有.db721文件），三者取其一
+pub fn db721_table_source(foreign_table_id: Oid) -> DB721Source {
+    if let Some(file_name) = db721_find_option_value(foreign_table_id, "filename") {
+        DB721Source::File(PathBuf::from(file_name))
+    } else if let Some(pattern) = db721_find_option_value(foreign_table_id, "filenames") {
+        DB721Source::Glob(pattern)
+    } else if let Some(dir) = db721_find_option_value(foreign_table_id, "directory") {
+        DB721Source::Directory(PathBuf::from(dir))
+    } else {
+        error!("db721 foreign table needs one of filename, filenames or directory option")
+    }
+}
+/// 外部表上可用的选项，tablename只用于说明，不会被读取
+const DB721_TABLE_OPTIONS: [&str; 4] = ["filename", "filenames", "directory", "tablename"];
+/// 数据来源选项，三者取其一
+const DB721_SOURCE_OPTIONS: [&str; 3] = ["filename", "filenames", "directory"];
+extension_sql!(
+    r#"
+CREATE FUNCTION db721_fdw_validator(options text[], catalog oid)
+RETURNS void
+LANGUAGE c STRICT
+AS 'MODULE_PATHNAME', 'db721_fdw_validator';
+"#,
+    name = "db721_fdw_validator",
+);
+#[no_mangle]
+pub extern "C" fn pg_finfo_db721_fdw_validator() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+/// db721_fdw_validator(options, catalog)：检查CREATE/ALTER中给出的选项。
+/// 外部表只接受DB721_TABLE_OPTIONS，列只接受virtual_column，wrapper、server和user mapping不接受选项；
+/// 与file_fdw相同，指定文件路径需要超级用户或pg_read_server_files角色的权限
+#[no_mangle]
+#[pg_guard]
+pub unsafe extern "C" fn db721_fdw_validator(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    // 没有选项时传入的是空指针而不是空数组
+    let options = pg_sys::untransformRelOptions(pg_getarg_datum(fcinfo, 0).unwrap());
+    let catalog = pg_getarg::<Oid>(fcinfo, 1).unwrap();
+    let option_count = if options.is_null() { 0 } else { (*options).length };
+    let mut source_option: Option<String> = None;
+    for i in 0..option_count {
+        let list_cell = l_nth_cell!(options, i) as *mut ListCell;
+        let option_def = l_first!(list_cell) as *mut DefElem;
+        let option_name = CStr::from_ptr((*option_def).defname).to_string_lossy().to_string();
+        let valid_options: &[&str] = if catalog == pg_sys::ForeignTableRelationId {
+            &DB721_TABLE_OPTIONS
+        } else if catalog == pg_sys::AttributeRelationId {
+            &["virtual_column"]
+        } else {
+            &[]
+        };
+        if !valid_options.contains(&option_name.as_str()) {
+            ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+                format!(
+                    "invalid option \"{}\", valid options in this context are: {}",
+                    option_name,
+                    valid_options.join(", ")
+                )
+            );
+        }
+        let option_value = CStr::from_ptr(defGetString(option_def)).to_string_lossy().to_string();
+        if option_name == "virtual_column"
+            && DB721VirtualColumn::from_option_value(&option_value).is_none()
+        {
+            ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_STRING_FORMAT,
+                format!(
+                    "invalid virtual_column {}, expect source_file, block_index or row_number",
+                    option_value
+                )
+            );
+        }
+        if DB721_SOURCE_OPTIONS.contains(&option_name.as_str()) {
+            if let Some(source_option) = &source_option {
+                ereport!(
+                    PgLogLevel::ERROR,
+                    PgSqlErrorCode::ERRCODE_SYNTAX_ERROR,
+                    format!(
+                        "conflicting or redundant options: {} and {}",
+                        source_option, option_name
+                    )
+                );
+            }
+            if !db721_has_role(READ_SERVER_FILES) {
+                ereport!(
+                    PgLogLevel::ERROR,
+                    PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+                    format!(
+                        "only superuser or a role with privileges of the {} role may specify the {} option of a db721 foreign table",
+                        READ_SERVER_FILES, option_name
+                    )
+                );
+            }
+            source_option = Some(option_name);
+        }
+    }
+    if catalog == pg_sys::ForeignTableRelationId && source_option.is_none() {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_FDW_DYNAMIC_PARAMETER_VALUE_NEEDED,
+            "db721 foreign table needs one of filename, filenames or directory option"
         );
     }
+    pg_sys::Datum::from(0)
 }
 /// from foreign_table's option, get the filename
 /// travel the options list, and match the key of option, return the value
@@ -430,6 +787,114 @@ pub extern "C" fn db721_column_list(base_rel: *mut RelOptInfo, foreign_table_id:
         column_list
     }
 }
+/// 供db721模块在block边界及批量解码中调用。取消查询时这里会抛出
+/// ERROR，rust栈被展开，扫描状态则由扫描内存上下文的重置回调释放
+pub fn db721_check_for_interrupts() {
+    check_for_interrupts!();
+}
+/// 通过numeric的输入函数构造decimal值。输入函数在第一个decimal值时查找，
+/// 之后与文本缓冲区一起在整个扫描中复用
+#[derive(Default)]
+pub struct DB721NumericInput {
+    input_function: Option<(FmgrInfo, Oid)>,
+    buffer: Vec<u8>,
+}
+
+impl DB721NumericInput {
+    /// 按列的typmod构造numeric，与输入函数一样检查精度
+    unsafe fn datum(&mut self, value: decimal::Decimal, typmod: i32) -> Datum {
+        let (flinfo, type_io_param) = self.input_function.get_or_insert_with(|| {
+            let mut type_input: Oid = pg_sys::InvalidOid;
+            let mut type_io_param: Oid = pg_sys::InvalidOid;
+            getTypeInputInfo(pg_sys::NUMERICOID, &mut type_input, &mut type_io_param);
+            let mut flinfo: FmgrInfo = std::mem::zeroed();
+            fmgr_info(type_input, &mut flinfo);
+            (flinfo, type_io_param)
+        });
+        self.buffer.clear();
+        write!(self.buffer, "{}\0", value).expect("write to Vec failed");
+        InputFunctionCall(flinfo, self.buffer.as_mut_ptr() as *mut c_char, *type_io_param, typmod)
+    }
+}
+
+/// 将batch中第value_idx个值转换为Datum，变长类型分配在当前内存上下文中。
+/// NULL需要调用方先用is_null判断，type_oid为目标列的类型，用于区分binary列对应的uuid和bytea，
+/// typmod传给decimal值的numeric输入函数
+pub fn db721_batch_datum(
+    batch: &BlockBatch,
+    value_idx: usize,
+    type_oid: Oid,
+    typmod: i32,
+    numeric_input: &mut DB721NumericInput,
+) -> Datum {
+    match batch.values() {
+        BatchValues::Str(_) | BatchValues::Dict(..) | BatchValues::Var(..) => {
+            // 直接从block缓冲区拷贝一次到varlena中
+            let str_bytes = batch.str_bytes(value_idx);
+            let text_p = unsafe {
+                cstring_to_text_with_len(
+                    str_bytes.as_ptr() as *const c_char,
+                    str_bytes.len() as c_int,
+                )
+            };
+            Datum::from(text_p)
+        }
+        BatchValues::Integer(values) => Datum::from(values[value_idx]),
+        BatchValues::BigInt(values) => Datum::from(values[value_idx]),
+        BatchValues::Float(values) => Datum::from(u32::from_ne_bytes(values[value_idx].to_ne_bytes())),
+        BatchValues::Double(values) => Datum::from(u64::from_ne_bytes(values[value_idx].to_ne_bytes())),
+        BatchValues::Bool(values) => Datum::from(values[value_idx]),
+        BatchValues::Date(values) => Datum::from(datetime::date_to_pg(values[value_idx])),
+        BatchValues::Timestamp(values) => Datum::from(datetime::timestamp_to_pg(values[value_idx])),
+        BatchValues::Binary(..) => unsafe {
+            let bytes = batch.binary_bytes(value_idx);
+            if type_oid == pg_sys::UUIDOID {
+                // uuid按引用传递，内容就是16个字节
+                let uuid_p = pg_sys::palloc(bytes.len()) as *mut u8;
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), uuid_p, bytes.len());
+                Datum::from(uuid_p)
+            } else {
+                // bytea与text的varlena布局相同
+                Datum::from(cstring_to_text_with_len(bytes.as_ptr() as *const c_char, bytes.len() as c_int))
+            }
+        },
+        BatchValues::Decimal(values, scale) => unsafe {
+            numeric_input.datum(decimal::Decimal::new(values[value_idx], *scale), typmod)
+        },
+    }
+}
+/// db721的列类型能否直接转换为type_oid对应的pg类型，decimal(p,s)对应numeric(p,s)或不限精度的numeric，
+/// binary(16)对应uuid，binary(n)都可以对应bytea
+pub fn db721_type_matches(value_type: &str, type_oid: Oid, typmod: i32) -> bool {
+    if let Some(width) = binary::params(value_type) {
+        return type_oid == pg_sys::BYTEAOID || (type_oid == pg_sys::UUIDOID && width == binary::UUID_WIDTH);
+    }
+    if let Some((precision, scale)) = decimal::params(value_type) {
+        return type_oid == pg_sys::NUMERICOID
+            && (typmod == -1 || typmod == (((precision as i32) << 16) | scale as i32) + VARHDRSZ as i32);
+    }
+    match value_type {
+        "int" => type_oid == pg_sys::INT4OID,
+        "bigint" => type_oid == pg_sys::INT8OID,
+        "float" => type_oid == pg_sys::FLOAT4OID,
+        "double" => type_oid == pg_sys::FLOAT8OID,
+        "bool" => type_oid == pg_sys::BOOLOID,
+        "date" => type_oid == pg_sys::DATEOID,
+        "timestamp" => type_oid == pg_sys::TIMESTAMPTZOID,
+        "str" => type_oid == pg_sys::TEXTOID || type_oid == pg_sys::VARCHAROID,
+        _ => false,
+    }
+}
+/// 按列类型（int2/int4/int8）构造整数Datum
+fn db721_integer_datum(value: i64, type_oid: Oid) -> Datum {
+    if type_oid == pg_sys::INT8OID {
+        Datum::from(value)
+    } else if type_oid == pg_sys::INT2OID {
+        Datum::from(value as i16)
+    } else {
+        Datum::from(value as i32)
+    }
+}
 /// 读取下一行
 #[pg_guard]
 pub extern "C" fn db721_read_next_row(
@@ -438,45 +903,82 @@ pub extern "C" fn db721_read_next_row(
     column_nulls: *mut bool,
 ) -> bool{
     unsafe {
-        // set all column_null to true
-        memset(
-            column_nulls as *mut c_void,
-            c_int::from(true),
-            (*((*scan_state).column_list)).length as size_t * size_of::<bool>()
-        );
-        for index in 0..(*((*scan_state).column_list)).length{
-            let list_cell = l_nth_cell!((*scan_state).column_list, index) as *mut ListCell;
-            let column = l_first!(list_cell) as *mut Var;
-            let column_index = (*column).varattno - 1;
-            let column_name = (*scan_state).column_index_map_name.get(&column_index)
-                .expect("get column name by column index failed");
-            let column_iterator = (*scan_state).column_iterators.get_mut(column_name)
-                .expect("get column iterator by column name failed");
-            let next_val = column_iterator.next();
-            match next_val{
-                None => {
+        check_for_interrupts!();
+        loop {
+            // 当前文件读取完毕，切换到下一个文件
+            if (*scan_state).is_end() && !(*scan_state).open_next_file() {
+                return false;
+            }
+            // set all column_null to true
+            memset(
+                column_nulls as *mut c_void,
+                c_int::from(true),
+                (*((*scan_state).column_list)).length as size_t * size_of::<bool>()
+            );
+            let row_position = (*scan_state)
+                .row_tracker
+                .as_mut()
+                .and_then(|row_tracker| row_tracker.advance());
+            for index in 0..(*((*scan_state).column_list)).length{
+                let list_cell = l_nth_cell!((*scan_state).column_list, index) as *mut ListCell;
+                let column = l_first!(list_cell) as *mut Var;
+                let column_index = (*column).varattno - 1;
+                if let Some(datum) = (*scan_state).partition_datums.get(&column_index) {
+                    *(column_values.add(column_index as usize)) = *datum;
+                    *(column_nulls.add(column_index as usize)) = false;
                     continue;
-                },
-                Some(DB721Type::Str(str)) => {
-                    let text_p =  pgrx::rust_str_to_text_p(str.as_str());
-                    *(column_values.add(column_index as usize)) = Datum::from(text_p.into_pg());
-                },
-                Some(DB721Type::Integer(val))  => {
-                    let p_int = palloc0(size_of::<i32>()) as *mut i32;
-                    *p_int = val;
-                    *(column_values.add(column_index as usize)) = Datum::from(val);
-                },
-                Some(DB721Type::Float(val)) => {
-                    let res = u32::from_ne_bytes(val.to_ne_bytes());
-                    *(column_values.add(column_index as usize)) = Datum::from(res);
                 }
-            };
-            *(column_nulls.add(column_index as usize)) = false;
-        }
-        // 只有全部都获取到的none才算获取完毕。
-        if (*scan_state).is_end(){
-            return false
+                if let Some((virtual_column, type_oid)) =
+                    (*scan_state).virtual_columns.get(&column_index).copied()
+                {
+                    let Some((block_idx, row_number)) = row_position else {
+                        continue;
+                    };
+                    *(column_values.add(column_index as usize)) = match virtual_column {
+                        DB721VirtualColumn::SourceFile => {
+                            let path = (*scan_state).current_file().path.to_string_lossy();
+                            Datum::from(cstring_to_text_with_len(
+                                path.as_ptr() as *const c_char,
+                                path.len() as c_int,
+                            ))
+                        }
+                        DB721VirtualColumn::BlockIndex => {
+                            db721_integer_datum(block_idx as i64, type_oid)
+                        }
+                        DB721VirtualColumn::RowNumber => {
+                            db721_integer_datum(row_number as i64, type_oid)
+                        }
+                    };
+                    *(column_nulls.add(column_index as usize)) = false;
+                    continue;
+                }
+                let column_name = (*scan_state).column_index_map_name.get(&column_index)
+                    .expect("get column name by column index failed");
+                let column_cursor = (*scan_state).column_cursors.get_mut(column_name)
+                    .expect("get column cursor by column name failed");
+                let (batch, value_idx) = match column_cursor.advance() {
+                    None => {
+                        continue;
+                    },
+                    Some(next_val) => next_val,
+                };
+                // 文件中的NULL保持column_nulls中的true
+                if batch.is_null(value_idx) {
+                    continue;
+                }
+                *(column_values.add(column_index as usize)) = db721_batch_datum(
+                    batch,
+                    value_idx,
+                    (*column).vartype,
+                    (*column).vartypmod,
+                    &mut (*scan_state).numeric_input,
+                );
+                *(column_nulls.add(column_index as usize)) = false;
+            }
+            // 只有全部都获取到的none才算当前文件获取完毕。
+            if (*scan_state).column_cursors.is_empty() || !(*scan_state).is_end(){
+                return true
+            }
         }
     }
-    true
 }
@@ -0,0 +1,1043 @@
+use crate::db721::binary::Binary;
+/// 可以直接在SQL中调用的db721文件工具函数，不需要创建外部表
+use crate::db721::check::check_file;
+use crate::db721::csv_import::{import_csv, parse_schema, CsvImportOptions};
+use crate::db721::writer::DB721Writer;
+use crate::db721::{
+    binary, datetime, decimal, Codec, ColumnCursor, ColumnIteratorBuilder, DB721Type, DB721,
+};
+use crate::db721rs_fdw_scan::{
+    db721_batch_datum, db721_check_for_interrupts, db721_type_matches, DB721NumericInput,
+};
+use anyhow::Context;
+use pgrx::prelude::*;
+use pgrx::spi::SpiHeapTupleData;
+use pgrx::{
+    ereport, extension_sql, pg_arg_is_null, pg_getarg, pg_getarg_datum, AnyNumeric, PgLogLevel,
+    PgSqlErrorCode, Uuid,
+};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// 读取服务器文件需要的预定义角色
+pub(crate) const READ_SERVER_FILES: &str = "pg_read_server_files";
+/// 写入服务器文件需要的预定义角色
+const WRITE_SERVER_FILES: &str = "pg_write_server_files";
+/// 函数以数据库服务器的OS用户读写任意路径，与COPY相同，
+/// 要求调用者是超级用户或拥有role_name（READ_SERVER_FILES / WRITE_SERVER_FILES）的权限
+fn db721_require_role(role_name: &str, function_name: &str) {
+    if db721_has_role(role_name) {
+        return;
+    }
+    ereport!(
+        PgLogLevel::ERROR,
+        PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+        format!(
+            "must be superuser or have privileges of the {} role to call {}",
+            role_name, function_name
+        )
+    );
+}
+/// 当前用户是超级用户或拥有role_name的权限
+pub(crate) fn db721_has_role(role_name: &str) -> bool {
+    unsafe {
+        if pg_sys::superuser() {
+            return true;
+        }
+        let role = CString::new(role_name).unwrap();
+        let role_id = pg_sys::get_role_oid(role.as_ptr(), false);
+        pg_sys::has_privs_of_role(pg_sys::GetUserId(), role_id)
+    }
+}
+fn db721_open(filename: &str) -> DB721 {
+    DB721::open(PathBuf::from(filename))
+        .with_context(|| format!("failed to open {}", filename))
+        .unwrap_or_else(|e| error!("{}", e))
+}
+/// 文件的footer信息，每一列一行，按列在文件中的存储顺序排列
+#[pg_extern]
+fn db721_metadata(
+    filename: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(table_name, String),
+        name!(max_values_per_block, i32),
+        name!(column_name, String),
+        name!(column_type, String),
+        name!(start_offset, i32),
+        name!(num_blocks, i32),
+        name!(codec, String),
+    ),
+> {
+    db721_require_role(READ_SERVER_FILES, "db721_metadata");
+    let db721 = db721_open(filename);
+    let rows: Vec<_> = db721
+        .meta
+        .sorted_columns()
+        .into_iter()
+        .map(|(column_name, column_meta)| {
+            (
+                db721.meta.table_name().to_string(),
+                db721.meta.max_value_per_block(),
+                column_name.clone(),
+                column_meta.value_type().to_string(),
+                column_meta.start_offset(),
+                column_meta.num_blocks(),
+                column_meta.codec().name().to_string(),
+            )
+        })
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+/// 每个(列, block)一行，给出footer中记录的block统计信息
+#[pg_extern]
+fn db721_block_stats(
+    filename: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(column_name, String),
+        name!(block_index, i32),
+        name!(num, i32),
+        name!(min, String),
+        name!(max, String),
+        name!(min_len, Option<i32>),
+        name!(max_len, Option<i32>),
+        name!(compressed_size, Option<i32>),
+        name!(encoding, String),
+        name!(null_count, i32),
+        name!(bloom_size, Option<i32>),
+    ),
+> {
+    db721_require_role(READ_SERVER_FILES, "db721_block_stats");
+    let db721 = db721_open(filename);
+    let mut rows = Vec::new();
+    for (column_name, column_meta) in db721.meta.sorted_columns() {
+        for block_idx in 0..column_meta.num_blocks() {
+            let Some(block_meta) = column_meta.block_meta(block_idx) else {
+                continue;
+            };
+            rows.push((
+                column_name.clone(),
+                block_idx,
+                block_meta.value_num(),
+                block_meta.min().to_string(),
+                block_meta.max().to_string(),
+                block_meta.min_len(),
+                block_meta.max_len(),
+                block_meta.compressed_size(),
+                block_meta.encoding().name().to_string(),
+                block_meta.null_count(),
+                block_meta.bloom().map(|bloom| bloom.size() as i32),
+            ));
+        }
+    }
+    TableIterator::new(rows.into_iter())
+}
+/// 导出时postgres类型对应的db721类型，numeric需要给出精度，numeric(p,s)对应decimal(p,s)，
+/// uuid对应binary(16)
+fn db721_value_type_of(type_oid: pg_sys::Oid, typmod: i32) -> Option<String> {
+    let value_type = match type_oid {
+        pg_sys::INT2OID | pg_sys::INT4OID => "int",
+        pg_sys::INT8OID => "bigint",
+        pg_sys::FLOAT4OID => "float",
+        pg_sys::FLOAT8OID => "double",
+        pg_sys::BOOLOID => "bool",
+        pg_sys::DATEOID => "date",
+        pg_sys::TIMESTAMPTZOID => "timestamp",
+        pg_sys::TEXTOID | pg_sys::VARCHAROID => "str",
+        pg_sys::UUIDOID => return Some(binary::type_name(binary::UUID_WIDTH)),
+        pg_sys::NUMERICOID if typmod >= pg_sys::VARHDRSZ as i32 => {
+            let typmod = typmod - pg_sys::VARHDRSZ as i32;
+            let value_type =
+                decimal::type_name(((typmod >> 16) & 0xffff) as u32, (typmod & 0x7ff) as u32);
+            // 精度超过38或scale为负数时不支持
+            return decimal::params(&value_type).map(|_| value_type);
+        }
+        _ => return None,
+    };
+    Some(value_type.to_string())
+}
+/// SPI最近一次取出的结果中第ordinal列的typmod
+unsafe fn db721_spi_typmod(ordinal: usize) -> i32 {
+    let tuple_table = pg_sys::SPI_tuptable;
+    if tuple_table.is_null() || (*tuple_table).tupdesc.is_null() {
+        return -1;
+    }
+    let attr_forms =
+        (*(*tuple_table).tupdesc).attrs.as_mut_ptr() as *mut pg_sys::FormData_pg_attribute;
+    (*attr_forms.add(ordinal - 1)).atttypmod
+}
+/// 默认用文件名（不含扩展名）作为表名
+fn db721_table_name_of(filename: &str) -> String {
+    Path::new(filename)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(filename)
+        .to_string()
+}
+/// 按列的postgres类型取出一行中的值，NULL为None
+fn db721_spi_value(
+    row: &SpiHeapTupleData,
+    ordinal: usize,
+    column_name: &str,
+    type_oid: pg_sys::Oid,
+) -> Option<DB721Type> {
+    let value = match type_oid {
+        pg_sys::INT2OID => row
+            .get::<i16>(ordinal)
+            .map(|val| val.map(|val| DB721Type::Integer(val as i32))),
+        pg_sys::INT4OID => row
+            .get::<i32>(ordinal)
+            .map(|val| val.map(DB721Type::Integer)),
+        pg_sys::INT8OID => row
+            .get::<i64>(ordinal)
+            .map(|val| val.map(DB721Type::BigInt)),
+        pg_sys::FLOAT4OID => row.get::<f32>(ordinal).map(|val| val.map(DB721Type::Float)),
+        pg_sys::FLOAT8OID => row
+            .get::<f64>(ordinal)
+            .map(|val| val.map(DB721Type::Double)),
+        pg_sys::BOOLOID => row.get::<bool>(ordinal).map(|val| val.map(DB721Type::Bool)),
+        pg_sys::DATEOID => row
+            .get::<pgrx::Date>(ordinal)
+            .map(|val| val.map(|val| db721_date_value(val.to_pg_epoch_days(), column_name))),
+        pg_sys::TIMESTAMPTZOID => row
+            .get::<pgrx::TimestampWithTimeZone>(ordinal)
+            .map(|val| val.map(|val| db721_timestamp_value(i64::from(val), column_name))),
+        // 转为字符串后由writer按列的scale转换
+        pg_sys::NUMERICOID => row
+            .get::<AnyNumeric>(ordinal)
+            .map(|val| val.map(|val| DB721Type::Str(val.to_string()))),
+        pg_sys::UUIDOID => row
+            .get::<Uuid>(ordinal)
+            .map(|val| val.map(|val| DB721Type::Binary(Binary::new(val.as_bytes().to_vec())))),
+        _ => row
+            .get::<String>(ordinal)
+            .map(|val| val.map(DB721Type::Str)),
+    };
+    value.unwrap_or_else(|e| error!("failed to read column {}: {}", column_name, e))
+}
+/// infinity无法写入db721文件
+fn db721_date_value(pg_days: i32, column_name: &str) -> DB721Type {
+    match datetime::date_from_pg(pg_days) {
+        Some(days) => DB721Type::Date(days),
+        None => error!("column {} has an infinite date", column_name),
+    }
+}
+fn db721_timestamp_value(pg_micros: i64, column_name: &str) -> DB721Type {
+    match datetime::timestamp_from_pg(pg_micros) {
+        Some(micros) => DB721Type::Timestamp(micros),
+        None => error!("column {} has an infinite timestamp", column_name),
+    }
+}
+fn db721_codec_of(codec: &str) -> Codec {
+    Codec::from_name(codec).unwrap_or_else(|e| error!("{}", e))
+}
+/// 执行查询并把结果写入新的db721文件，返回写入的行数。
+/// 结果列只能是int2/int4/int8、float4/float8、bool、date、timestamptz、numeric(p,s)、uuid、text/varchar，
+/// 其余类型需要在查询中转换
+#[pg_extern]
+fn db721_export(
+    query: &str,
+    filename: &str,
+    max_values_per_block: default!(i32, 50000),
+    codec: default!(&str, "'none'"),
+    encode: default!(bool, false),
+    var_str: default!(bool, false),
+    bloom: default!(Option<Vec<String>>, "NULL"),
+) -> i64 {
+    const FETCH_SIZE: i64 = 1000;
+    db721_require_role(WRITE_SERVER_FILES, "db721_export");
+    let codec = db721_codec_of(codec);
+    Spi::connect(|client| {
+        let mut cursor = client.open_cursor(query, None);
+        let mut writer: Option<DB721Writer> = None;
+        let mut columns: Vec<(String, pg_sys::Oid)> = Vec::new();
+        loop {
+            let tuple_table = cursor
+                .fetch(FETCH_SIZE)
+                .unwrap_or_else(|e| error!("failed to fetch rows: {}", e));
+            if writer.is_none() {
+                // 第一批结果确定文件的列
+                let column_count = tuple_table
+                    .columns()
+                    .unwrap_or_else(|e| error!("query returns no result set: {}", e));
+                let mut db721_columns = Vec::with_capacity(column_count);
+                for ordinal in 1..=column_count {
+                    let column_name = tuple_table
+                        .column_name(ordinal)
+                        .unwrap_or_else(|e| error!("{}", e));
+                    let type_oid = tuple_table
+                        .column_type_oid(ordinal)
+                        .unwrap_or_else(|e| error!("{}", e))
+                        .value();
+                    let typmod = unsafe { db721_spi_typmod(ordinal) };
+                    let Some(value_type) = db721_value_type_of(type_oid, typmod) else {
+                        error!(
+                            "column {} has a type db721 cannot store, cast it to int, real, numeric(p,s) or text",
+                            column_name
+                        );
+                    };
+                    db721_columns.push((column_name.clone(), value_type));
+                    columns.push((column_name, type_oid));
+                }
+                let mut db721_writer = DB721Writer::create(
+                    PathBuf::from(filename),
+                    db721_table_name_of(filename),
+                    max_values_per_block,
+                    db721_columns,
+                )
+                .unwrap_or_else(|e| error!("{}", e));
+                db721_writer.set_codec(codec);
+                db721_writer.set_auto_encoding(encode);
+                db721_writer.set_var_str(var_str);
+                db721_writer
+                    .set_bloom_columns(bloom.as_deref().unwrap_or_default())
+                    .unwrap_or_else(|e| error!("{}", e));
+                writer = Some(db721_writer);
+            }
+            let writer = writer.as_mut().unwrap();
+            let mut fetched = 0;
+            for row in tuple_table {
+                let values: Vec<Option<DB721Type>> = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, (column_name, type_oid))| {
+                        db721_spi_value(&row, idx + 1, column_name, *type_oid)
+                    })
+                    .collect();
+                writer
+                    .write_nullable_row(&values)
+                    .unwrap_or_else(|e| error!("{}", e));
+                fetched += 1;
+            }
+            if fetched < FETCH_SIZE {
+                break;
+            }
+        }
+        let writer = writer.unwrap();
+        let row_count = writer.row_count() as i64;
+        writer
+            .finish()
+            .with_context(|| format!("failed to write {}", filename))
+            .unwrap_or_else(|e| error!("{}", e));
+        row_count
+    })
+}
+/// 把服务器上的CSV文件转换为db721文件，返回写入的行数。
+/// columns形如 'identifier:int,farm_name:str'，为空时根据前sample_rows行推断
+#[pg_extern]
+fn db721_from_csv(
+    csv_path: &str,
+    out_path: &str,
+    columns: default!(Option<&str>, "NULL"),
+    max_values_per_block: default!(i32, 50000),
+    sample_rows: default!(i32, 1000),
+    delimiter: default!(&str, "','"),
+    header: default!(bool, true),
+    codec: default!(&str, "'none'"),
+    encode: default!(bool, false),
+    var_str: default!(bool, false),
+    bloom: default!(Option<Vec<String>>, "NULL"),
+) -> i64 {
+    db721_require_role(READ_SERVER_FILES, "db721_from_csv");
+    db721_require_role(WRITE_SERVER_FILES, "db721_from_csv");
+    let [delimiter] = delimiter.as_bytes() else {
+        error!("delimiter should be a single byte");
+    };
+    let columns = columns.map(|columns| parse_schema(columns).unwrap_or_else(|e| error!("{}", e)));
+    let options = CsvImportOptions {
+        table_name: None,
+        max_values_per_block,
+        columns,
+        sample_rows: sample_rows.max(1) as usize,
+        delimiter: *delimiter,
+        has_header: header,
+        codec: db721_codec_of(codec),
+        auto_encoding: encode,
+        var_str,
+        bloom_columns: bloom.unwrap_or_default(),
+    };
+    import_csv(Path::new(csv_path), PathBuf::from(out_path), &options)
+        .with_context(|| format!("failed to convert {}", csv_path))
+        .unwrap_or_else(|e| error!("{:#}", e)) as i64
+}
+/// 检查文件的完整性，每个问题一行，文件没有问题时不返回任何行
+#[pg_extern]
+fn db721_check(
+    filename: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(column_name, Option<String>),
+        name!(block_index, Option<i32>),
+        name!(message, String),
+    ),
+> {
+    db721_require_role(READ_SERVER_FILES, "db721_check");
+    let rows: Vec<_> = check_file(PathBuf::from(filename))
+        .into_iter()
+        .map(|finding| (finding.column_name, finding.block_idx, finding.message))
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION db721_read(filename text, columns text[] DEFAULT NULL, row_limit bigint DEFAULT NULL)
+RETURNS SETOF record
+LANGUAGE c CALLED ON NULL INPUT
+AS 'MODULE_PATHNAME', 'db721_read_srf';
+"#,
+    name = "db721_read",
+);
+#[no_mangle]
+pub extern "C" fn pg_finfo_db721_read_srf() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+/// db721_read(filename, columns, row_limit)：不创建外部表，直接读取文件。
+/// 返回的列由调用方的列定义列表给出，例如
+/// `select * from db721_read('/path/to.db721') as t(identifier int, farm_name text)`，
+/// 默认按列名匹配文件中的列，给出columns时按位置匹配columns中的列名
+#[no_mangle]
+#[pg_guard]
+pub unsafe extern "C" fn db721_read_srf(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    db721_require_role(READ_SERVER_FILES, "db721_read");
+    let rsinfo = (*fcinfo).resultinfo as *mut pg_sys::ReturnSetInfo;
+    if rsinfo.is_null()
+        || (*rsinfo).type_ != pg_sys::NodeTag_T_ReturnSetInfo
+        || ((*rsinfo).allowedModes & pg_sys::SetFunctionReturnMode_SFRM_Materialize as c_int) == 0
+    {
+        error!("db721_read must be called in a context that accepts a set");
+    }
+    let mut tuple_desc: pg_sys::TupleDesc = ptr::null_mut();
+    if pg_sys::get_call_result_type(fcinfo, ptr::null_mut(), &mut tuple_desc)
+        != pg_sys::TypeFuncClass_TYPEFUNC_COMPOSITE
+    {
+        error!(
+            "db721_read needs a column definition list, e.g. AS t(identifier int, farm_name text)"
+        );
+    }
+    let Some(filename) = pg_getarg::<String>(fcinfo, 0) else {
+        error!("db721_read needs a filename");
+    };
+    let columns = pg_getarg::<Vec<Option<String>>>(fcinfo, 1);
+    let row_limit = pg_getarg::<i64>(fcinfo, 2);
+    let column_count = (*tuple_desc).natts as usize;
+    if let Some(columns) = &columns {
+        if columns.len() != column_count {
+            error!(
+                "columns has {} entries, but the column definition list has {} columns",
+                columns.len(),
+                column_count
+            );
+        }
+    }
+    let db721 = db721_open(&filename);
+    // 为每个输出列找到文件中对应的列并创建游标
+    let mut column_cursors = Vec::with_capacity(column_count);
+    for attr_idx in 0..column_count {
+        let attr_form =
+            ((*tuple_desc).attrs.as_mut_ptr() as *mut pg_sys::FormData_pg_attribute).add(attr_idx);
+        let column_name = match &columns {
+            Some(columns) => match columns.get(attr_idx) {
+                Some(Some(column_name)) => column_name.clone(),
+                _ => error!(
+                    "columns has a NULL entry for output column {}",
+                    attr_idx + 1
+                ),
+            },
+            None => CStr::from_ptr((*attr_form).attname.data.as_ptr())
+                .to_str()
+                .expect("column name should be valid UTF-8")
+                .to_string(),
+        };
+        let Some(column_meta) = db721.meta.column_meta.get(&column_name) else {
+            error!("column {} does not exist in {}", column_name, filename);
+        };
+        if !db721_type_matches(
+            column_meta.value_type(),
+            (*attr_form).atttypid,
+            (*attr_form).atttypmod,
+        ) {
+            error!(
+                "column {} is {} in {}, which does not match the declared type",
+                column_name,
+                column_meta.value_type(),
+                filename
+            );
+        }
+        let mut column_iterator_builder =
+            ColumnIteratorBuilder::new(column_meta.clone(), column_name, db721.path.clone());
+        column_iterator_builder.set_interrupt_check(db721_check_for_interrupts);
+        let column_iterator = column_iterator_builder.build().unwrap();
+        column_cursors.push((
+            ColumnCursor::new(column_iterator),
+            (*attr_form).atttypid,
+            (*attr_form).atttypmod,
+        ));
+    }
+    // 结果tuplestore及其描述符需要在per-query上下文中存活到调用方读取完毕
+    let old_memory_context = pg_sys::CurrentMemoryContext;
+    pg_sys::CurrentMemoryContext = (*(*rsinfo).econtext).ecxt_per_query_memory;
+    let tuple_desc = pg_sys::CreateTupleDescCopy(tuple_desc);
+    // 只有调用方需要随机访问时才让tuplestore支持反向读取
+    let random_access = ((*rsinfo).allowedModes
+        & pg_sys::SetFunctionReturnMode_SFRM_Materialize_Random as c_int)
+        != 0;
+    let tuple_store = pg_sys::tuplestore_begin_heap(random_access, false, pg_sys::work_mem);
+    pg_sys::CurrentMemoryContext = old_memory_context;
+    // 每一行的变长值分配在单独的上下文中，写入tuplestore后即可重置
+    let row_memory_context = pg_sys::AllocSetContextCreateInternal(
+        pg_sys::CurrentMemoryContext,
+        b"db721_read row\0".as_ptr() as *const c_char,
+        pg_sys::ALLOCSET_DEFAULT_MINSIZE as pg_sys::Size,
+        pg_sys::ALLOCSET_DEFAULT_INITSIZE as pg_sys::Size,
+        pg_sys::ALLOCSET_DEFAULT_MAXSIZE as pg_sys::Size,
+    );
+    let mut values = vec![pg_sys::Datum::from(0usize); column_count];
+    let mut nulls = vec![true; column_count];
+    let mut numeric_input = DB721NumericInput::default();
+    let mut row_count: i64 = 0;
+    while row_limit.is_none_or(|row_limit| row_count < row_limit) {
+        pg_sys::MemoryContextReset(row_memory_context);
+        pg_sys::CurrentMemoryContext = row_memory_context;
+        let mut found = false;
+        for (attr_idx, (column_cursor, type_oid, typmod)) in column_cursors.iter_mut().enumerate() {
+            match column_cursor.advance() {
+                Some((batch, value_idx)) => {
+                    nulls[attr_idx] = batch.is_null(value_idx);
+                    if !nulls[attr_idx] {
+                        values[attr_idx] = db721_batch_datum(
+                            batch,
+                            value_idx,
+                            *type_oid,
+                            *typmod,
+                            &mut numeric_input,
+                        );
+                    }
+                    found = true;
+                }
+                None => nulls[attr_idx] = true,
+            }
+        }
+        pg_sys::CurrentMemoryContext = old_memory_context;
+        if !found {
+            break;
+        }
+        pg_sys::tuplestore_putvalues(
+            tuple_store,
+            tuple_desc,
+            values.as_mut_ptr(),
+            nulls.as_mut_ptr(),
+        );
+        row_count += 1;
+    }
+    pg_sys::MemoryContextDelete(row_memory_context);
+    (*rsinfo).returnMode = pg_sys::SetFunctionReturnMode_SFRM_Materialize;
+    (*rsinfo).setResult = tuple_store;
+    (*rsinfo).setDesc = tuple_desc;
+    pg_sys::Datum::from(0usize)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION db721_agg_transfn(internal, text, record)
+RETURNS internal
+LANGUAGE c CALLED ON NULL INPUT
+AS 'MODULE_PATHNAME', 'db721_agg_transfn';
+CREATE FUNCTION db721_agg_transfn(internal, text, record, integer)
+RETURNS internal
+LANGUAGE c CALLED ON NULL INPUT
+AS 'MODULE_PATHNAME', 'db721_agg_transfn';
+CREATE FUNCTION db721_agg_finalfn(internal)
+RETURNS bigint
+LANGUAGE c CALLED ON NULL INPUT
+AS 'MODULE_PATHNAME', 'db721_agg_finalfn';
+CREATE AGGREGATE db721_agg(filename text, "row" record) (
+    SFUNC = db721_agg_transfn,
+    STYPE = internal,
+    FINALFUNC = db721_agg_finalfn,
+    FINALFUNC_MODIFY = READ_WRITE
+);
+CREATE AGGREGATE db721_agg(filename text, "row" record, max_values_per_block integer) (
+    SFUNC = db721_agg_transfn,
+    STYPE = internal,
+    FINALFUNC = db721_agg_finalfn,
+    FINALFUNC_MODIFY = READ_WRITE
+);
+"#,
+    name = "db721_agg",
+);
+/// db721_agg的聚合状态，分配在聚合的内存上下文中，
+/// 上下文被重置或删除时通过回调释放（未完成的writer会删除临时文件）。
+/// 分组的行先保存在内存中，到最终函数才创建writer，
+/// 因此同时进行的分组再多也不会各自占用临时文件
+struct DB721AggState {
+    filename: String,
+    columns: Vec<(String, pg_sys::Oid)>,
+    /// 文件的列名和db721类型
+    db721_columns: Vec<(String, String)>,
+    max_values_per_block: i32,
+    /// 分组中的行，最终函数取走之后为None
+    rows: Option<Vec<Vec<Option<DB721Type>>>>,
+    /// 最终函数写文件期间的writer
+    writer: Option<DB721Writer>,
+    row_count: i64,
+}
+/// 释放聚合状态，最终函数中途出错时writer在Drop中删除临时文件
+#[pg_guard]
+unsafe extern "C" fn db721_agg_state_reset_callback(arg: *mut c_void) {
+    let _ = std::mem::replace(
+        &mut *(arg as *mut Option<DB721AggState>),
+        Option::<DB721AggState>::None,
+    );
+}
+/// 把一个Datum按postgres类型转换为db721的值
+unsafe fn db721_datum_value(
+    datum: pg_sys::Datum,
+    column_name: &str,
+    type_oid: pg_sys::Oid,
+) -> DB721Type {
+    let value = match type_oid {
+        pg_sys::INT2OID => i16::from_datum(datum, false).map(|val| DB721Type::Integer(val as i32)),
+        pg_sys::INT4OID => i32::from_datum(datum, false).map(DB721Type::Integer),
+        pg_sys::INT8OID => i64::from_datum(datum, false).map(DB721Type::BigInt),
+        pg_sys::FLOAT4OID => f32::from_datum(datum, false).map(DB721Type::Float),
+        pg_sys::FLOAT8OID => f64::from_datum(datum, false).map(DB721Type::Double),
+        pg_sys::BOOLOID => bool::from_datum(datum, false).map(DB721Type::Bool),
+        pg_sys::DATEOID => {
+            i32::from_datum(datum, false).map(|val| db721_date_value(val, column_name))
+        }
+        pg_sys::TIMESTAMPTZOID => {
+            i64::from_datum(datum, false).map(|val| db721_timestamp_value(val, column_name))
+        }
+        pg_sys::NUMERICOID => {
+            AnyNumeric::from_datum(datum, false).map(|val| DB721Type::Str(val.to_string()))
+        }
+        pg_sys::UUIDOID => Uuid::from_datum(datum, false)
+            .map(|val| DB721Type::Binary(Binary::new(val.as_bytes().to_vec()))),
+        _ => String::from_datum(datum, false).map(DB721Type::Str),
+    };
+    value.unwrap_or_else(|| error!("failed to read column {}", column_name))
+}
+#[no_mangle]
+pub extern "C" fn pg_finfo_db721_agg_transfn() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+/// db721_agg(filename, row[, max_values_per_block])的状态转移函数，
+/// 第一行决定文件的列名和类型，之后每一行保存在状态中
+#[no_mangle]
+#[pg_guard]
+pub unsafe extern "C" fn db721_agg_transfn(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let mut agg_context: pg_sys::MemoryContext = ptr::null_mut();
+    if pg_sys::AggCheckCallContext(fcinfo, &mut agg_context) == 0 {
+        error!("db721_agg_transfn called in non-aggregate context");
+    }
+    let Some(filename) = pg_getarg::<String>(fcinfo, 1) else {
+        error!("db721_agg needs a filename");
+    };
+    if pg_arg_is_null(fcinfo, 2) {
+        error!("db721_agg cannot write a NULL row");
+    }
+    let max_values_per_block = if (*fcinfo).nargs > 3 {
+        pg_getarg::<i32>(fcinfo, 3).unwrap_or(50000)
+    } else {
+        50000
+    };
+    let header = pg_sys::pg_detoast_datum(
+        pg_getarg_datum(fcinfo, 2)
+            .unwrap()
+            .cast_mut_ptr::<pg_sys::varlena>(),
+    ) as pg_sys::HeapTupleHeader;
+    let tuple_desc = pg_sys::lookup_rowtype_tupdesc(
+        (*header).t_choice.t_datum.datum_typeid,
+        (*header).t_choice.t_datum.datum_typmod,
+    );
+    let column_count = (*tuple_desc).natts as usize;
+    let attr_forms = (*tuple_desc).attrs.as_mut_ptr() as *mut pg_sys::FormData_pg_attribute;
+
+    let p_state = if pg_arg_is_null(fcinfo, 0) {
+        // 第一行，把状态挂在聚合上下文上
+        db721_require_role(WRITE_SERVER_FILES, "db721_agg");
+        if max_values_per_block <= 0 {
+            error!("max values per block should be positive");
+        }
+        let mut columns = Vec::with_capacity(column_count);
+        let mut db721_columns = Vec::with_capacity(column_count);
+        for attr_idx in 0..column_count {
+            let attr_form = attr_forms.add(attr_idx);
+            if (*attr_form).attisdropped {
+                continue;
+            }
+            let column_name = CStr::from_ptr((*attr_form).attname.data.as_ptr())
+                .to_str()
+                .expect("column name should be valid UTF-8")
+                .to_string();
+            let Some(value_type) =
+                db721_value_type_of((*attr_form).atttypid, (*attr_form).atttypmod)
+            else {
+                error!(
+                    "column {} has a type db721 cannot store, cast it to int, real, numeric(p,s) or text",
+                    column_name
+                );
+            };
+            db721_columns.push((column_name.clone(), value_type));
+            columns.push((column_name, (*attr_form).atttypid));
+        }
+        let p_state = pg_sys::MemoryContextAlloc(
+            agg_context,
+            size_of::<Option<DB721AggState>>() as pg_sys::Size,
+        ) as *mut Option<DB721AggState>;
+        ptr::write(
+            p_state,
+            Some(DB721AggState {
+                filename,
+                columns,
+                db721_columns,
+                max_values_per_block,
+                rows: Some(Vec::new()),
+                writer: None,
+                row_count: 0,
+            }),
+        );
+        let reset_callback = pg_sys::MemoryContextAlloc(
+            agg_context,
+            size_of::<pg_sys::MemoryContextCallback>() as pg_sys::Size,
+        ) as *mut pg_sys::MemoryContextCallback;
+        (*reset_callback).func = Some(db721_agg_state_reset_callback);
+        (*reset_callback).arg = p_state as *mut c_void;
+        (*reset_callback).next = ptr::null_mut();
+        pg_sys::MemoryContextRegisterResetCallback(agg_context, reset_callback);
+        p_state
+    } else {
+        pg_getarg_datum(fcinfo, 0)
+            .unwrap()
+            .cast_mut_ptr::<Option<DB721AggState>>()
+    };
+    let state = (*p_state).as_mut().unwrap();
+    if state.filename != filename {
+        error!(
+            "db721_agg writes {} in this group, but got filename {}",
+            state.filename, filename
+        );
+    }
+    let Some(rows) = state.rows.as_mut() else {
+        error!("db721_agg cannot be used as a window function");
+    };
+
+    let mut tuple: pg_sys::HeapTupleData = std::mem::zeroed();
+    tuple.t_len = (*header).t_choice.t_datum.datum_len_ as u32;
+    tuple.t_data = header;
+    let mut values = vec![pg_sys::Datum::from(0usize); column_count];
+    let mut nulls = vec![false; column_count];
+    pg_sys::heap_deform_tuple(
+        &mut tuple,
+        tuple_desc,
+        values.as_mut_ptr(),
+        nulls.as_mut_ptr(),
+    );
+    let mut row = Vec::with_capacity(state.columns.len());
+    let mut column_iter = state.columns.iter();
+    for attr_idx in 0..column_count {
+        if (*attr_forms.add(attr_idx)).attisdropped {
+            continue;
+        }
+        let Some((column_name, type_oid)) = column_iter.next() else {
+            error!("db721_agg got rows of different types");
+        };
+        if *type_oid != (*attr_forms.add(attr_idx)).atttypid {
+            error!("db721_agg got rows of different types");
+        }
+        if nulls[attr_idx] {
+            row.push(None);
+        } else {
+            row.push(Some(db721_datum_value(
+                values[attr_idx],
+                column_name,
+                *type_oid,
+            )));
+        }
+    }
+    if (*tuple_desc).tdrefcount >= 0 {
+        pg_sys::DecrTupleDescRefCount(tuple_desc);
+    }
+    rows.push(row);
+    state.row_count += 1;
+    pg_sys::Datum::from(p_state)
+}
+#[no_mangle]
+pub extern "C" fn pg_finfo_db721_agg_finalfn() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+/// 写完文件并返回行数，分组中没有行时返回NULL且不生成文件
+#[no_mangle]
+#[pg_guard]
+pub unsafe extern "C" fn db721_agg_finalfn(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    if pg_arg_is_null(fcinfo, 0) {
+        (*fcinfo).isnull = true;
+        return pg_sys::Datum::from(0usize);
+    }
+    let p_state = pg_getarg_datum(fcinfo, 0)
+        .unwrap()
+        .cast_mut_ptr::<Option<DB721AggState>>();
+    let state = (*p_state).as_mut().unwrap();
+    if let Some(rows) = state.rows.take() {
+        db721_require_role(WRITE_SERVER_FILES, "db721_agg");
+        // writer挂在状态上，写入中途出错时由重置回调释放并删除临时文件
+        let writer = state.writer.insert(
+            DB721Writer::create(
+                PathBuf::from(&state.filename),
+                db721_table_name_of(&state.filename),
+                state.max_values_per_block,
+                std::mem::take(&mut state.db721_columns),
+            )
+            .unwrap_or_else(|e| error!("{}", e)),
+        );
+        for row in rows.iter() {
+            db721_check_for_interrupts();
+            writer
+                .write_nullable_row(row)
+                .unwrap_or_else(|e| error!("{}", e));
+        }
+        state
+            .writer
+            .take()
+            .unwrap()
+            .finish()
+            .with_context(|| format!("failed to write {}", state.filename))
+            .unwrap_or_else(|e| error!("{}", e));
+    }
+    pg_sys::Datum::from(state.row_count)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION db721_load(filename text, target regclass)
+RETURNS bigint
+LANGUAGE c STRICT
+AS 'MODULE_PATHNAME', 'db721_load';
+"#,
+    name = "db721_load",
+);
+#[no_mangle]
+pub extern "C" fn pg_finfo_db721_load() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+/// db721_load(filename, target)：把文件的全部行通过表访问方法批量插入到普通表中，返回插入的行数。
+/// 按列名对应，文件中没有的列使用默认值，生成列按表达式计算；会维护索引并检查约束，
+/// 但不会触发触发器，也不支持行级安全策略和可推迟的约束
+#[no_mangle]
+#[pg_guard]
+pub unsafe extern "C" fn db721_load(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    db721_require_role(READ_SERVER_FILES, "db721_load");
+    let filename = pg_getarg::<String>(fcinfo, 0).unwrap();
+    let relation_id = pg_getarg::<pg_sys::Oid>(fcinfo, 1).unwrap();
+    let relation = pg_sys::relation_open(relation_id, pg_sys::RowExclusiveLock as pg_sys::LOCKMODE);
+    let relation_name = CStr::from_ptr((*(*relation).rd_rel).relname.data.as_ptr())
+        .to_string_lossy()
+        .to_string();
+    if (*(*relation).rd_rel).relkind as u8 != pg_sys::RELKIND_RELATION {
+        error!("{} is not a table", relation_name);
+    }
+    if pg_sys::pg_class_aclcheck(
+        relation_id,
+        pg_sys::GetUserId(),
+        pg_sys::ACL_INSERT as pg_sys::AclMode,
+    ) != pg_sys::AclResult_ACLCHECK_OK
+    {
+        error!("permission denied for table {}", relation_name);
+    }
+    if !(*relation).trigdesc.is_null() {
+        error!(
+            "db721_load does not fire triggers, but {} has triggers",
+            relation_name
+        );
+    }
+    // 逐行插入不经过查询改写，无法应用行级安全策略
+    if pg_sys::check_enable_rls(relation_id, pg_sys::InvalidOid, false)
+        == pg_sys::CheckEnableRlsResult_RLS_ENABLED as c_int
+    {
+        error!(
+            "db721_load does not apply row-level security policies, but {} has row-level security enabled",
+            relation_name
+        );
+    }
+    let db721 = db721_open(&filename);
+    let tuple_desc = (*relation).rd_att;
+    let column_count = (*tuple_desc).natts as usize;
+    // 为每个目标列找到文件中的同名列，没有同名列时使用列的默认值
+    let mut column_cursors: Vec<Option<(ColumnCursor, pg_sys::Oid, i32)>> =
+        Vec::with_capacity(column_count);
+    let mut column_defaults: Vec<(usize, *mut pg_sys::ExprState)> = Vec::new();
+    for attr_idx in 0..column_count {
+        let attr_form =
+            ((*tuple_desc).attrs.as_mut_ptr() as *mut pg_sys::FormData_pg_attribute).add(attr_idx);
+        if (*attr_form).attisdropped {
+            column_cursors.push(None);
+            continue;
+        }
+        let column_name = CStr::from_ptr((*attr_form).attname.data.as_ptr())
+            .to_str()
+            .expect("column name should be valid UTF-8")
+            .to_string();
+        // 生成列在插入前按表达式计算，忽略文件中的同名列
+        let is_generated = (*attr_form).attgenerated != 0;
+        let column_meta = if is_generated {
+            None
+        } else {
+            db721.meta.column_meta.get(&column_name)
+        };
+        let Some(column_meta) = column_meta else {
+            if !is_generated {
+                let default_expr = pg_sys::build_column_default(relation, attr_idx as c_int + 1);
+                if !default_expr.is_null() {
+                    let default_expr =
+                        pg_sys::expression_planner(default_expr as *mut pg_sys::Expr);
+                    column_defaults.push((
+                        attr_idx,
+                        pg_sys::ExecInitExpr(default_expr, ptr::null_mut()),
+                    ));
+                }
+            }
+            column_cursors.push(None);
+            continue;
+        };
+        if !db721_type_matches(
+            column_meta.value_type(),
+            (*attr_form).atttypid,
+            (*attr_form).atttypmod,
+        ) {
+            error!(
+                "column {} is {} in {}, which does not match the type in {}",
+                column_name,
+                column_meta.value_type(),
+                filename,
+                relation_name
+            );
+        }
+        let mut column_iterator_builder =
+            ColumnIteratorBuilder::new(column_meta.clone(), column_name, db721.path.clone());
+        column_iterator_builder.set_interrupt_check(db721_check_for_interrupts);
+        let column_iterator = column_iterator_builder.build().unwrap();
+        column_cursors.push(Some((
+            ColumnCursor::new(column_iterator),
+            (*attr_form).atttypid,
+            (*attr_form).atttypmod,
+        )));
+    }
+    if column_cursors
+        .iter()
+        .all(|column_cursor| column_cursor.is_none())
+    {
+        error!("{} has no column of {}", filename, relation_name);
+    }
+    // 索引维护和约束检查需要executor state
+    let estate = pg_sys::CreateExecutorState();
+    let result_rel_info =
+        pg_sys::palloc0(size_of::<pg_sys::ResultRelInfo>()) as *mut pg_sys::ResultRelInfo;
+    (*result_rel_info).type_ = pg_sys::NodeTag_T_ResultRelInfo;
+    // 没有range table，下标为0
+    pg_sys::InitResultRelInfo(result_rel_info, relation, 0, ptr::null_mut(), 0);
+    pg_sys::ExecOpenIndices(result_rel_info, false);
+    // 可推迟的唯一约束和排他约束需要在事务提交前重新检查，这里没有after trigger可以排队
+    for index_idx in 0..(*result_rel_info).ri_NumIndices as usize {
+        let index_relation = *(*result_rel_info).ri_IndexRelationDescs.add(index_idx);
+        if !(*(*index_relation).rd_index).indimmediate {
+            error!(
+                "db721_load cannot defer constraint checks, but {} has deferrable unique or exclusion constraints",
+                relation_name
+            );
+        }
+    }
+    let per_tuple_context = pg_sys::MakePerTupleExprContext(estate);
+    let slot = pg_sys::table_slot_create(relation, ptr::null_mut());
+    let bulk_insert_state = pg_sys::GetBulkInsertState();
+    let command_id = pg_sys::GetCurrentCommandId(true);
+    let table_am = (*relation).rd_tableam;
+    let has_constraints = !(*tuple_desc).constr.is_null();
+    let has_generated_stored = has_constraints && (*(*tuple_desc).constr).has_generated_stored;
+
+    let old_memory_context = pg_sys::CurrentMemoryContext;
+    let mut numeric_input = DB721NumericInput::default();
+    let mut row_count: i64 = 0;
+    loop {
+        pg_sys::MemoryContextReset((*per_tuple_context).ecxt_per_tuple_memory);
+        pg_sys::CurrentMemoryContext = (*per_tuple_context).ecxt_per_tuple_memory;
+        (*(*slot).tts_ops).clear.unwrap()(slot);
+        let values = (*slot).tts_values;
+        let nulls = (*slot).tts_isnull;
+        let mut found = false;
+        for (attr_idx, column_cursor) in column_cursors.iter_mut().enumerate() {
+            *nulls.add(attr_idx) = true;
+            let Some((column_cursor, type_oid, typmod)) = column_cursor else {
+                continue;
+            };
+            if let Some((batch, value_idx)) = column_cursor.advance() {
+                if !batch.is_null(value_idx) {
+                    *values.add(attr_idx) =
+                        db721_batch_datum(batch, value_idx, *type_oid, *typmod, &mut numeric_input);
+                    *nulls.add(attr_idx) = false;
+                }
+                found = true;
+            }
+        }
+        if !found {
+            pg_sys::CurrentMemoryContext = old_memory_context;
+            break;
+        }
+        for (attr_idx, default_expr) in column_defaults.iter() {
+            let mut is_null = false;
+            *values.add(*attr_idx) = (*(*default_expr)).evalfunc.unwrap()(
+                *default_expr,
+                per_tuple_context,
+                &mut is_null,
+            );
+            *nulls.add(*attr_idx) = is_null;
+        }
+        pg_sys::ExecStoreVirtualTuple(slot);
+        if has_generated_stored {
+            pg_sys::ExecComputeStoredGenerated(
+                result_rel_info,
+                estate,
+                slot,
+                pg_sys::CmdType_CMD_INSERT,
+            );
+        }
+        if has_constraints {
+            pg_sys::ExecConstraints(result_rel_info, slot, estate);
+        }
+        (*table_am).tuple_insert.unwrap()(relation, slot, command_id, 0, bulk_insert_state);
+        if (*result_rel_info).ri_NumIndices > 0 {
+            let recheck_indexes = pg_sys::ExecInsertIndexTuples(
+                result_rel_info,
+                slot,
+                estate,
+                false,
+                false,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            // 已经拒绝了可推迟的约束，不会有需要重新检查的索引
+            pg_sys::list_free(recheck_indexes);
+        }
+        pg_sys::CurrentMemoryContext = old_memory_context;
+        row_count += 1;
+    }
+    pg_sys::FreeBulkInsertState(bulk_insert_state);
+    if let Some(finish_bulk_insert) = (*table_am).finish_bulk_insert {
+        finish_bulk_insert(relation, 0);
+    }
+    pg_sys::ExecDropSingleTupleTableSlot(slot);
+    pg_sys::ExecCloseIndices(result_rel_info);
+    pg_sys::FreeExecutorState(estate);
+    pg_sys::relation_close(relation, pg_sys::NoLock as pg_sys::LOCKMODE);
+    pg_sys::Datum::from(row_count)
+}
@@ -1,5 +1,7 @@
 mod db721;
+mod db721rs_fdw_qual;
 mod db721rs_fdw_scan;
+mod db721rs_functions;
 mod storage;
 
 use crate::db721rs_fdw_scan::{
@@ -7,61 +9,9 @@ use crate::db721rs_fdw_scan::{
     db721_get_foreign_plan, db721_get_foreign_rel_size, db721_iterate_foreign_scan,
 };
 use pgrx::prelude::*;
-use serde::{Deserialize, Serialize};
 
 pgrx::pg_module_magic!();
 
-#[derive(Serialize, Deserialize, PostgresType)]
-pub struct MyType {
-    values: Vec<String>,
-    thing: Option<Box<MyType>>,
-}
-
-#[pg_extern]
-fn push_value(mut input: MyType, value: String) -> MyType {
-    input.values.push(value);
-    input
-}
-
-#[derive(Serialize, Deserialize, PostgresType)]
-pub struct MyPoint {
-    x: i32,
-    y: i32,
-}
-
-#[pg_extern]
-fn test_my_point(pt: MyPoint) -> MyPoint {
-    pt
-}
-
-#[pg_extern]
-fn hello_pg_hello_world() -> &'static str {
-    "Hello, pg_hello_world"
-}
-
-#[pg_extern]
-fn test_params(i: i32, strs: &str) -> String {
-    format!("i is {i} and strs is {strs}")
-}
-
-#[pg_extern]
-fn test_vec(arr: Vec<i32>) -> String {
-    let mut res = String::from("[");
-    for i in arr {
-        res.push_str(format!("{i},").as_str());
-    }
-    res.pop();
-    if !res.is_empty() {
-        res.push_str("]");
-    }
-    res
-}
-
-#[pg_extern]
-fn hello_world() -> &'static str {
-    "hello, world!"
-}
-
 #[pg_extern]
 unsafe fn db721_fdw_handler() -> PgBox<pg_sys::FdwRoutine> {
     let mut fdw_routine = PgBox::<pg_sys::FdwRoutine>::alloc_node(pg_sys::NodeTag_T_FdwRoutine);
@@ -78,9 +28,103 @@ unsafe fn db721_fdw_handler() -> PgBox<pg_sys::FdwRoutine> {
 mod tests {
     use pgrx::prelude::*;
 
+    #[pg_test(error = "failed to open /nonexistent/data-chickens.db721")]
+    fn test_db721_metadata_missing_file() {
+        Spi::run("SELECT * FROM db721_metadata('/nonexistent/data-chickens.db721')").unwrap();
+    }
+
+    #[pg_test(error = "columns has 1 entries, but the column definition list has 2 columns")]
+    fn test_db721_read_columns_length_mismatch() {
+        Spi::run(
+            "SELECT * FROM db721_read('/nonexistent/data-chickens.db721', columns => array['identifier'])
+                AS t(id integer, weight real)",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "conflicting or redundant options: filename and directory")]
+    fn test_db721_fdw_validator_conflicting_options() {
+        Spi::run(
+            "CREATE FOREIGN DATA WRAPPER db721_validator_wrapper
+                HANDLER db721_fdw_handler VALIDATOR db721_fdw_validator;
+            CREATE SERVER db721_validator_server FOREIGN DATA WRAPPER db721_validator_wrapper;
+            CREATE FOREIGN TABLE db721_validator_table (identifier integer)
+                SERVER db721_validator_server
+                OPTIONS (filename '/tmp/chickens.db721', directory '/tmp/chickens')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_db721_load_column_defaults_and_generated() {
+        Spi::run(
+            "SELECT db721_export('SELECT i AS identifier FROM generate_series(1, 3) i',
+                '/tmp/db721_load_defaults.db721');
+            CREATE TABLE db721_load_defaults (
+                identifier integer,
+                farm_name text DEFAULT 'unknown',
+                doubled integer GENERATED ALWAYS AS (identifier * 2) STORED)",
+        )
+        .unwrap();
+        let rows = Spi::get_one::<i64>(
+            "SELECT db721_load('/tmp/db721_load_defaults.db721', 'db721_load_defaults')",
+        )
+        .unwrap();
+        assert_eq!(rows, Some(3));
+        let loaded = Spi::get_one::<i64>(
+            "SELECT count(*) FROM db721_load_defaults
+            WHERE farm_name = 'unknown' AND doubled = identifier * 2",
+        )
+        .unwrap();
+        assert_eq!(loaded, Some(3));
+        std::fs::remove_file("/tmp/db721_load_defaults.db721").unwrap();
+    }
+
+    #[pg_test(
+        error = "db721_load does not apply row-level security policies, but db721_load_rls has row-level security enabled"
+    )]
+    fn test_db721_load_row_level_security() {
+        // 超级用户总是绕过行级安全策略，需要切换到普通用户
+        Spi::run(
+            "CREATE TABLE public.db721_load_rls (identifier integer);
+            ALTER TABLE public.db721_load_rls ENABLE ROW LEVEL SECURITY;
+            CREATE ROLE db721_load_rls_user IN ROLE pg_read_server_files;
+            GRANT INSERT ON public.db721_load_rls TO db721_load_rls_user;
+            SET ROLE db721_load_rls_user;
+            SELECT db721_load('/nonexistent/data-chickens.db721', 'public.db721_load_rls')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(
+        error = "db721_load cannot defer constraint checks, but db721_load_deferrable has deferrable unique or exclusion constraints"
+    )]
+    fn test_db721_load_deferrable_constraint() {
+        Spi::run(
+            "SELECT db721_export('SELECT i AS identifier FROM generate_series(1, 3) i',
+                '/tmp/db721_load_deferrable.db721');
+            CREATE TABLE db721_load_deferrable (identifier integer UNIQUE DEFERRABLE);
+            SELECT db721_load('/tmp/db721_load_deferrable.db721', 'db721_load_deferrable')",
+        )
+        .unwrap();
+    }
+
     #[pg_test]
-    fn test_hello_pg_hello_world() {
-        assert_eq!("Hello, pg_hello_world", crate::hello_pg_hello_world());
+    fn test_db721_agg_many_groups() {
+        // 分组的行到最终函数才写出，同时进行的分组不会各自打开临时文件
+        let rows = Spi::get_one::<i64>(
+            "SELECT sum(n)::bigint FROM (
+                SELECT db721_agg('/tmp/db721_agg_group_' || g || '.db721', t) AS n
+                FROM (SELECT i % 2000 AS g, i AS identifier FROM generate_series(1, 6000) i) t
+                GROUP BY g) s",
+        )
+        .unwrap();
+        assert_eq!(rows, Some(6000));
+        for g in 0..2000 {
+            let path = format!("/tmp/db721_agg_group_{}.db721", g);
+            assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+            std::fs::remove_file(path).unwrap();
+        }
     }
 }
 